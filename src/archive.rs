@@ -0,0 +1,812 @@
+use crate::{error::Error, lzma};
+
+const RAR4: &[u8] = b"Rar!\x1a\x07\x00";
+const RAR5: &[u8] = b"Rar!\x1a\x07\x01\x00";
+const SEVEN_ZIP: &[u8] = b"7z\xbc\xaf\x27\x1c";
+
+const LZMA: &[u8] = &[0x03, 0x01, 0x01];
+const COPY: &[u8] = &[0x00];
+const AES: &[u8] = &[0x06, 0xf1, 0x07, 0x01];
+
+/// Largest encoded header unpacked, real ones hold a few bytes per file.
+const HEADER_LIMIT: u64 = 64 << 20;
+
+/// A file as listed by an archive's headers.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Listed {
+    pub name: String,
+    pub size: u64,
+    pub crc: u32,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|x| x[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes(4)
+            .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+    }
+
+    /// RAR5 variable length integer, 7 bits per byte with the high bit set on all but the last.
+    fn vint(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for i in 0..10 {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// 7z number, the leading one bits of the first byte count the bytes that follow.
+    fn number(&mut self) -> Option<u64> {
+        let first = self.u8()?;
+        let mut value = 0;
+        for i in 0..8 {
+            let mask = 0x80 >> i;
+            if first & mask == 0 {
+                return Some(value | ((first & (mask - 1)) as u64) << (8 * i));
+            }
+            value |= (self.u8()? as u64) << (8 * i);
+        }
+        Some(value)
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        self.number()?.try_into().ok()
+    }
+
+    /// 7z bit vector, most significant bit first.
+    fn bits(&mut self, len: usize) -> Option<Vec<bool>> {
+        let bytes = self.bytes(len.div_ceil(8))?;
+        Some(
+            (0..len)
+                .map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+                .collect(),
+        )
+    }
+
+    /// 7z CRC32 digests of `len` streams, some of which may be undefined.
+    fn digests(&mut self, len: usize) -> Option<Vec<Option<u32>>> {
+        let defined = match self.u8()? {
+            0 => self.bits(len)?,
+            _ if len > self.remaining() / 4 => return None,
+            _ => vec![true; len],
+        };
+        defined
+            .into_iter()
+            .map(|x| match x {
+                true => self.u32().map(Some),
+                false => Some(None),
+            })
+            .collect()
+    }
+}
+
+/// Whether the first bytes of a file start a RAR or 7z archive.
+pub fn is_archive(head: &[u8]) -> bool {
+    [RAR5, RAR4, SEVEN_ZIP].iter().any(|x| head.starts_with(x))
+}
+
+/// Lists the files of a RAR or 7z archive with their sizes and CRC32s, `None` for any other
+/// format.
+pub fn list(data: &[u8]) -> Result<Option<Vec<Listed>>, Error> {
+    let files = if data.starts_with(RAR5) {
+        rar5(data)?
+    } else if data.starts_with(RAR4) {
+        rar4(data)?
+    } else if data.starts_with(SEVEN_ZIP) {
+        seven_zip(data)?
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(files))
+}
+
+fn rar4(data: &[u8]) -> Result<Vec<Listed>, Error> {
+    let mut files = Vec::new();
+    let mut pos = RAR4.len();
+    while pos < data.len() {
+        let mut r = Reader::new(data, pos);
+        let (kind, flags, size, add) =
+            rar4_block(&mut r, &mut files).ok_or(Error::RarHeader(pos))?;
+        match kind {
+            0x73 if flags & 0x0080 != 0 => return Err(Error::RarHeader(pos)),
+            0x7b => break,
+            _ => pos = pos.saturating_add(size).saturating_add(add),
+        }
+    }
+
+    Ok(files)
+}
+
+/// Reads one block, listing it when it is a file, and returns its type, flags, header size and
+/// data size.
+fn rar4_block(r: &mut Reader, files: &mut Vec<Listed>) -> Option<(u8, u16, usize, usize)> {
+    let header = r.bytes(7)?;
+    let (kind, flags) = (header[2], u16::from_le_bytes([header[3], header[4]]));
+    let size = u16::from_le_bytes([header[5], header[6]]) as usize;
+    // File headers always carry their packed size here, other blocks only when flagged.
+    let mut add = match (kind, flags & 0x8000) {
+        (0x74, _) | (_, 0x8000) => r.u32()? as u64,
+        _ => 0,
+    };
+    if size < 7 {
+        return None;
+    }
+
+    if kind == 0x74 {
+        let mut unpacked = r.u32()? as u64;
+        let _host = r.u8()?;
+        let crc = r.u32()?;
+        let _time = r.u32()?;
+        let (_version, _method) = (r.u8()?, r.u8()?);
+        let name_size = u16::from_le_bytes(r.bytes(2)?.try_into().unwrap()) as usize;
+        let _attributes = r.u32()?;
+        if flags & 0x0100 != 0 {
+            add |= (r.u32()? as u64) << 32;
+            unpacked |= (r.u32()? as u64) << 32;
+        }
+        // Unicode names keep an ASCII copy before the NUL.
+        let name = r.bytes(name_size)?.split(|&x| x == 0).next()?;
+        let name = String::from_utf8_lossy(name).replace('\\', "/");
+
+        match (flags & 0x00e0 == 0x00e0, flags & 0x0003 != 0) {
+            (true, _) => (),
+            (false, true) => tracing::warn!("{} is split across volumes, skipped.", name),
+            (false, false) => files.push(Listed {
+                name,
+                size: unpacked,
+                crc,
+            }),
+        }
+    }
+
+    Some((kind, flags, size, add.try_into().ok()?))
+}
+
+fn rar5(data: &[u8]) -> Result<Vec<Listed>, Error> {
+    let mut files = Vec::new();
+    let mut pos = RAR5.len();
+    while pos < data.len() {
+        let mut r = Reader::new(data, pos);
+        match rar5_block(&mut r, &mut files).ok_or(Error::RarHeader(pos))? {
+            // Archive encryption header, everything after it is encrypted.
+            (4, _) => return Err(Error::RarHeader(pos)),
+            (5, _) => break,
+            (_, next) => pos = next,
+        }
+    }
+
+    Ok(files)
+}
+
+/// Reads one block, listing it when it is a file, and returns its type and where the next
+/// block starts.
+fn rar5_block(r: &mut Reader, files: &mut Vec<Listed>) -> Option<(u64, usize)> {
+    let _crc = r.u32()?;
+    let size = r.vint()? as usize;
+    let end = r.pos.checked_add(size)?;
+    let kind = r.vint()?;
+    let flags = r.vint()?;
+    let extra = match flags & 0x01 {
+        0 => 0,
+        _ => r.vint()? as usize,
+    };
+    let data = match flags & 0x02 {
+        0 => 0,
+        _ => r.vint()? as usize,
+    };
+
+    if kind == 2 {
+        let file_flags = r.vint()?;
+        let unpacked = r.vint()?;
+        let _attributes = r.vint()?;
+        if file_flags & 0x02 != 0 {
+            r.u32()?;
+        }
+        let crc = match file_flags & 0x04 {
+            0 => None,
+            _ => Some(r.u32()?),
+        };
+        let (_compression, _host) = (r.vint()?, r.vint()?);
+        let name_size = r.vint()? as usize;
+        let name = String::from_utf8_lossy(r.bytes(name_size)?).into_owned();
+
+        // An encryption record with tweaked checksums turns the CRC32 into an HMAC.
+        let mut tweaked = false;
+        let mut records = Reader::new(r.data.get(..end)?, end.checked_sub(extra)?);
+        while records.pos < end {
+            let record_size = records.vint()? as usize;
+            let next = records.pos.checked_add(record_size)?;
+            if records.vint()? == 1 {
+                let _version = records.vint()?;
+                tweaked |= records.vint()? & 0x02 != 0;
+            }
+            records.pos = next;
+        }
+
+        match crc {
+            _ if file_flags & 0x09 != 0 => (),
+            _ if flags & 0x18 != 0 => tracing::warn!("{} is split across volumes, skipped.", name),
+            _ if tweaked => {
+                tracing::warn!("CRC32 of {} is tweaked by its password, skipped.", name)
+            }
+            Some(crc) => files.push(Listed {
+                name,
+                size: unpacked,
+                crc,
+            }),
+            None => (),
+        }
+    }
+
+    Some((kind, end.checked_add(data)?))
+}
+
+#[derive(Debug, Default)]
+struct Folder {
+    coders: Vec<(Vec<u8>, Vec<u8>)>,
+    size: u64,
+    crc: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+struct Streams {
+    pack_pos: u64,
+    pack_sizes: Vec<u64>,
+    folders: Vec<Folder>,
+    /// Size and CRC32 of every file stream, folder by folder.
+    files: Vec<(u64, Option<u32>)>,
+}
+
+impl Streams {
+    fn parse(r: &mut Reader) -> Option<Self> {
+        let mut streams = Self::default();
+        let mut substreams = false;
+        loop {
+            match r.u8()? {
+                0x06 => {
+                    streams.pack_pos = r.number()?;
+                    let count = r.usize()?;
+                    loop {
+                        match r.u8()? {
+                            0x09 => {
+                                streams.pack_sizes =
+                                    (0..count).map(|_| r.number()).collect::<Option<_>>()?
+                            }
+                            0x0a => drop(r.digests(count)?),
+                            0x00 => break,
+                            _ => return None,
+                        }
+                    }
+                }
+                0x07 => streams.folders = Self::folders(r)?,
+                0x08 => {
+                    streams.files = Self::substreams(r, &streams.folders)?;
+                    substreams = true;
+                }
+                0x00 => break,
+                _ => return None,
+            }
+        }
+
+        if !substreams {
+            streams.files = streams.folders.iter().map(|x| (x.size, x.crc)).collect();
+        }
+        Some(streams)
+    }
+
+    fn folders(r: &mut Reader) -> Option<Vec<Folder>> {
+        if r.u8()? != 0x0b {
+            return None;
+        }
+        let count = r.usize()?;
+        if r.u8()? != 0 {
+            return None;
+        }
+
+        let mut folders = Vec::new();
+        let mut outs = Vec::new();
+        for _ in 0..count {
+            let mut folder = Folder::default();
+            let (mut total_in, mut total_out) = (0_usize, 0_usize);
+            for _ in 0..r.usize()? {
+                let flags = r.u8()?;
+                if flags & 0x80 != 0 {
+                    return None;
+                }
+                let id = r.bytes((flags & 0x0f) as usize)?.to_vec();
+                let (ins, outs) = match flags & 0x10 {
+                    0 => (1, 1),
+                    _ => (r.usize()?, r.usize()?),
+                };
+                let props = match flags & 0x20 {
+                    0 => Vec::new(),
+                    _ => {
+                        let len = r.usize()?;
+                        r.bytes(len)?.to_vec()
+                    }
+                };
+                folder.coders.push((id, props));
+                total_in = total_in.checked_add(ins)?;
+                total_out = total_out.checked_add(outs)?;
+            }
+
+            // Every output but the last is bound to another coder's input.
+            let bound = (1..total_out)
+                .map(|_| {
+                    let _input = r.usize()?;
+                    r.usize()
+                })
+                .collect::<Option<Vec<_>>>()?;
+            let packed = total_in.checked_add(1)?.checked_sub(total_out)?;
+            if packed > 1 {
+                (0..packed).try_for_each(|_| r.number().map(drop))?;
+            }
+            let main = (0..total_out).find(|x| !bound.contains(x))?;
+            outs.push((total_out, main));
+            folders.push(folder);
+        }
+
+        if r.u8()? != 0x0c {
+            return None;
+        }
+        for (folder, &(total_out, main)) in folders.iter_mut().zip(&outs) {
+            let sizes = (0..total_out)
+                .map(|_| r.number())
+                .collect::<Option<Vec<_>>>()?;
+            folder.size = sizes[main];
+        }
+        loop {
+            match r.u8()? {
+                0x0a => {
+                    let digests = r.digests(folders.len())?;
+                    folders.iter_mut().zip(digests).for_each(|(x, y)| x.crc = y);
+                }
+                0x00 => return Some(folders),
+                _ => return None,
+            }
+        }
+    }
+
+    fn substreams(r: &mut Reader, folders: &[Folder]) -> Option<Vec<(u64, Option<u32>)>> {
+        let mut counts = vec![1; folders.len()];
+        let mut id = r.u8()?;
+        if id == 0x0d {
+            counts = (0..folders.len())
+                .map(|_| r.usize())
+                .collect::<Option<_>>()?;
+            id = r.u8()?;
+        }
+
+        let mut files = Vec::new();
+        for (folder, &count) in folders.iter().zip(&counts) {
+            if count == 0 {
+                continue;
+            }
+            let mut sum = 0_u64;
+            if id == 0x09 {
+                for _ in 1..count {
+                    let size = r.number()?;
+                    sum = sum.checked_add(size)?;
+                    files.push((size, None));
+                }
+            }
+            files.push((folder.size.checked_sub(sum)?, None));
+            // A lone stream shares the CRC32 of its folder.
+            if count == 1 {
+                files.last_mut()?.1 = folder.crc;
+            }
+        }
+        if id == 0x09 {
+            id = r.u8()?;
+        }
+
+        loop {
+            match id {
+                0x0a => {
+                    let unknown = files.iter_mut().filter(|x| x.1.is_none());
+                    let unknown = unknown.collect::<Vec<_>>();
+                    let digests = r.digests(unknown.len())?;
+                    unknown
+                        .into_iter()
+                        .zip(digests)
+                        .for_each(|(x, crc)| x.1 = crc);
+                }
+                0x00 => return Some(files),
+                _ => return None,
+            }
+            id = r.u8()?;
+        }
+    }
+
+    /// Unpacks the first folder, which is all an encoded header has.
+    fn unpack(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let folder = self
+            .folders
+            .first()
+            .ok_or(Error::SevenZip("invalid encoded header"))?;
+        let start = usize::try_from(self.pack_pos)
+            .ok()
+            .and_then(|x| x.checked_add(32))
+            .ok_or(Error::SevenZip("invalid encoded header"))?;
+        let len = *self
+            .pack_sizes
+            .first()
+            .ok_or(Error::SevenZip("invalid encoded header"))? as usize;
+        let packed = data
+            .get(start..start.saturating_add(len))
+            .ok_or(Error::SevenZip("truncated encoded header"))?;
+
+        if folder.size > HEADER_LIMIT {
+            return Err(Error::SevenZip("encoded header too large"));
+        }
+
+        let unpacked = match folder.coders.as_slice() {
+            [(id, _)] if id == COPY => packed.to_vec(),
+            [(id, props)] if id == LZMA => lzma::decompress(props, packed, folder.size as usize)?,
+            coders if coders.iter().any(|x| x.0 == AES) => {
+                return Err(Error::SevenZip("header is encrypted"))
+            }
+            _ => return Err(Error::SevenZip("unsupported header coder")),
+        };
+        if folder.crc.is_some_and(|x| x != crc32fast::hash(&unpacked)) {
+            return Err(Error::SevenZip("encoded header CRC32 mismatch"));
+        }
+
+        Ok(unpacked)
+    }
+}
+
+fn seven_zip(data: &[u8]) -> Result<Vec<Listed>, Error> {
+    let signature = data
+        .get(..32)
+        .ok_or(Error::SevenZip("truncated signature header"))?;
+    let u32_at = |i: usize| u32::from_le_bytes(signature[i..i + 4].try_into().unwrap());
+    let u64_at = |i: usize| u64::from_le_bytes(signature[i..i + 8].try_into().unwrap());
+    if crc32fast::hash(&signature[12..]) != u32_at(8) {
+        return Err(Error::SevenZip("start header CRC32 mismatch"));
+    }
+
+    let (offset, size) = (u64_at(12), u64_at(20));
+    let start = usize::try_from(offset)
+        .ok()
+        .and_then(|x| x.checked_add(32))
+        .ok_or(Error::SevenZip("invalid next header offset"))?;
+    let header = data
+        .get(start..start.saturating_add(size as usize))
+        .ok_or(Error::SevenZip("truncated header"))?;
+    if crc32fast::hash(header) != u32_at(28) {
+        return Err(Error::SevenZip("next header CRC32 mismatch"));
+    }
+
+    // An encoded header unpacks to the plain one, never to another encoded header.
+    let mut r = Reader::new(header, 0);
+    let header = match r.u8() {
+        Some(0x01) => header.to_vec(),
+        Some(0x17) => Streams::parse(&mut r)
+            .ok_or(Error::SevenZip("invalid encoded header"))?
+            .unpack(data)?,
+        _ => return Err(Error::SevenZip("unknown header type")),
+    };
+    let mut r = Reader::new(&header, 0);
+    match r.u8() {
+        Some(0x01) => files(&mut r).ok_or(Error::SevenZip("invalid header")),
+        _ => Err(Error::SevenZip("unknown header type")),
+    }
+}
+
+/// Walks a plain header, pairing the names of non-empty files with their streams.
+fn files(r: &mut Reader) -> Option<Vec<Listed>> {
+    let mut streams = Streams::default();
+    let (mut names, mut empty) = (Vec::new(), Vec::new());
+    loop {
+        match r.u8()? {
+            0x02 => loop {
+                if r.u8()? == 0 {
+                    break;
+                }
+                let len = r.usize()?;
+                r.bytes(len)?;
+            },
+            0x03 => drop(Streams::parse(r)?),
+            0x04 => streams = Streams::parse(r)?,
+            0x05 => {
+                // Every file takes at least a byte of properties, which bounds the count.
+                let count = r.usize()?;
+                if count > r.remaining() {
+                    return None;
+                }
+                empty = vec![false; count];
+                loop {
+                    let kind = r.u8()?;
+                    if kind == 0 {
+                        break;
+                    }
+                    let len = r.usize()?;
+                    let mut property = Reader::new(r.bytes(len)?, 0);
+                    match kind {
+                        0x0e => empty = property.bits(count)?,
+                        0x11 if property.u8()? == 0 => {
+                            let utf16 = property.data[1..]
+                                .chunks_exact(2)
+                                .map(|x| u16::from_le_bytes([x[0], x[1]]))
+                                .collect::<Vec<_>>();
+                            names = utf16
+                                .split(|&x| x == 0)
+                                .take(count)
+                                .map(String::from_utf16_lossy)
+                                .collect();
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            0x00 => break,
+            _ => return None,
+        }
+    }
+
+    let names = names.into_iter().zip(empty).filter(|x| !x.1).map(|x| x.0);
+    Some(
+        names
+            .zip(streams.files)
+            .filter_map(|(name, (size, crc))| {
+                Some(Listed {
+                    name,
+                    size,
+                    crc: crc?,
+                })
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{list, Listed, RAR4, RAR5, SEVEN_ZIP};
+    use crate::error::Error;
+
+    const FILES: [(&str, &str); 3] = [("a", "fl"), ("b", "ag{"), ("dir/c", "rar}")];
+
+    fn listed(files: &[(&str, &str)]) -> Vec<Listed> {
+        files
+            .iter()
+            .map(|(name, pt)| Listed {
+                name: name.to_string(),
+                size: pt.len() as u64,
+                crc: crc32fast::hash(pt.as_bytes()),
+            })
+            .collect()
+    }
+
+    fn vint(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    #[test]
+    fn test_rar4() {
+        let block = |body: Vec<u8>| {
+            let crc = crc32fast::hash(&body) as u16;
+            [&crc.to_le_bytes()[..], &body].concat()
+        };
+        let mut data = RAR4.to_vec();
+        data.extend(block([&[0x73, 0, 0, 13, 0][..], &[0; 6]].concat()));
+        for (name, pt) in FILES {
+            let (len, crc) = (pt.len() as u32, crc32fast::hash(pt.as_bytes()));
+            data.extend(block(
+                [
+                    &[0x74, 0x00, 0x80][..],
+                    &(32 + name.len() as u16).to_le_bytes(),
+                    &len.to_le_bytes(),
+                    &len.to_le_bytes(),
+                    &[3],
+                    &crc.to_le_bytes(),
+                    &[0; 4],
+                    &[29, 0x30],
+                    &(name.len() as u16).to_le_bytes(),
+                    &[0; 4],
+                    name.as_bytes(),
+                ]
+                .concat(),
+            ));
+            data.extend_from_slice(pt.as_bytes());
+        }
+        data.extend(block(vec![0x7b, 0x00, 0x40, 7, 0]));
+
+        assert_eq!(list(&data).unwrap(), Some(listed(&FILES)));
+        assert!(list(&data[..data.len() - 12]).is_err());
+
+        // A directory, a stored file under it, a comment service block and another file, with
+        // the timestamps WinRAR adds, checked with `bsdtar -xf`.
+        let data = hex::decode(concat!(
+            "526172211a0700cf907300000d00000000000000ed5574e09025000000000000",
+            "00000002000000003c6b8e5a1d3003001000000064697200805f837400902e00",
+            "0a0000000a000000023c20f2743c6b8e5a1d300c00200000006469725c666c61",
+            "672e7478740080666c61677b726172347d98387a008023000700000007000000",
+            "026c5274943c6b8e5a1d30030000000000434d54636f6d6d656e7470fa740090",
+            "2a00020000000200000002ac2a93d83c6b8e5a1d300800200000006e6f74652e",
+            "74787400806869c43d7b00400700",
+        ))
+        .unwrap();
+        let files = [("dir/flag.txt", "flag{rar4}"), ("note.txt", "hi")];
+        assert_eq!(list(&data).unwrap(), Some(listed(&files)));
+    }
+
+    #[test]
+    fn test_rar5() {
+        let block = |body: Vec<u8>| {
+            let body = [vint(body.len()), body].concat();
+            [&crc32fast::hash(&body).to_le_bytes()[..], &body].concat()
+        };
+        let mut data = RAR5.to_vec();
+        data.extend(block(vec![1, 0, 0]));
+        for (name, pt) in FILES {
+            data.extend(block(
+                [
+                    &[2, 2][..],
+                    &vint(pt.len()),
+                    &[4],
+                    &vint(pt.len()),
+                    &vint(0o644),
+                    &crc32fast::hash(pt.as_bytes()).to_le_bytes(),
+                    &[0, 1],
+                    &vint(name.len()),
+                    name.as_bytes(),
+                ]
+                .concat(),
+            ));
+            data.extend_from_slice(pt.as_bytes());
+        }
+        data.extend(block(vec![5, 0, 0]));
+
+        assert_eq!(list(&data).unwrap(), Some(listed(&FILES)));
+        assert!(list(&data[..data.len() - 16]).is_err());
+
+        // A directory, a stored file under it, a comment service block and another file, with
+        // the timestamps WinRAR adds, checked with `bsdtar -xf`.
+        let data = hex::decode(concat!(
+            "526172211a0701009e91f35f07010103000201003e763b9c190201070300ed83",
+            "0100105e5f00010364697206030300105e5f35871c99270203070a060aa48302",
+            "00105e5f7d11e96d00010c6469722f666c61672e74787406030300105e5f666c",
+            "61677b726172357d6d76cf6c1c0303070706070000105e5f6c52749400010343",
+            "4d5406030300105e5f636f6d6d656e7435b31b5f23020307020602a483020010",
+            "5e5fac2a93d80001086e6f74652e74787406030300105e5f686919b23a350305",
+            "0000",
+        ))
+        .unwrap();
+        let files = [("dir/flag.txt", "flag{rar5}"), ("note.txt", "hi")];
+        assert_eq!(list(&data).unwrap(), Some(listed(&files)));
+    }
+
+    #[test]
+    fn test_seven_zip() {
+        // `bsdtar --format 7zip` of a, b, c and d/e, with an LZMA encoded header.
+        let data = hex::decode(concat!(
+            "377abcaf271c00038af3b14d9b000000000000002100000000000000c4f6da25",
+            "00331b0847584568346fa509c11dbb73f271b6ef9efefe183b298afffb3a4000",
+            "0000813307ae0fd00eb03c9f3f47416737d676e65eeeea04e9e0dd6a4e12a26a",
+            "9be9d08556f4a89d474c550859c2acc1fcee92252b3709c699fd1bfcbb2e9002",
+            "7ea3f49801c00fd05c2cdfba432fdf69a4444c519559e30dbd0f4b353aca30d7",
+            "9def162731670d09c6f281890ca1c292aa22b50ea9cfdffdb620001706200109",
+            "7b00070b01000123030101055d000080000c80f30a017984cb990000",
+        ))
+        .unwrap();
+        let files = [
+            ("a", "fl"),
+            ("b", "ag{"),
+            ("c", "7z}"),
+            ("d/e", "longer content"),
+        ];
+        assert_eq!(list(&data).unwrap(), Some(listed(&files)));
+
+        let mut corrupted = data.clone();
+        corrupted[0xcb] ^= 1;
+        assert!(list(&corrupted).is_err());
+        assert_eq!(list(b"PK\x03\x04").unwrap(), None);
+    }
+
+    /// Signature header and `packed` streams followed by `header`, with valid CRCs.
+    fn seven_zip(packed: &[u8], header: &[u8]) -> Vec<u8> {
+        let next = [
+            &(packed.len() as u64).to_le_bytes()[..],
+            &(header.len() as u64).to_le_bytes(),
+            &crc32fast::hash(header).to_le_bytes(),
+        ]
+        .concat();
+        let start = crc32fast::hash(&next).to_le_bytes();
+        [SEVEN_ZIP, &[0, 4], &start, &next, packed, header].concat()
+    }
+
+    #[test]
+    fn test_seven_zip_crafted() {
+        // An LZMA encoded header claiming an unpack size of `u64::MAX`.
+        let mut header = vec![
+            0x17, 0x06, 0x00, 0x01, 0x09, 0x05, 0x00, 0x07, 0x0b, 0x01, 0x00,
+        ];
+        header.extend([
+            0x01, 0x23, 0x03, 0x01, 0x01, 0x05, 0x5d, 0x00, 0x00, 0x01, 0x00, 0x0c,
+        ]);
+        header.extend([0xff; 9]);
+        header.extend([0x00, 0x00]);
+        assert!(matches!(
+            list(&seven_zip(&[0; 5], &header)),
+            Err(Error::SevenZip("encoded header too large"))
+        ));
+
+        // A copied header that points at itself.
+        let header = [
+            0x17, 0x06, 0x00, 0x01, 0x09, 18, 0x00, 0x07, 0x0b, 0x01, 0x00, 0x01, 0x01, 0x00, 0x0c,
+            18, 0x00, 0x00,
+        ];
+        let mut data = seven_zip(&[], &header);
+        assert!(matches!(
+            list(&data),
+            Err(Error::SevenZip("unknown header type"))
+        ));
+
+        data[28] ^= 1;
+        assert!(matches!(
+            list(&data),
+            Err(Error::SevenZip("start header CRC32 mismatch"))
+        ));
+        let mut data = seven_zip(&[], &header);
+        *data.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            list(&data),
+            Err(Error::SevenZip("next header CRC32 mismatch"))
+        ));
+
+        // Coders whose input counts add up past `usize::MAX`.
+        let mut header = vec![0x17, 0x07, 0x0b, 0x01, 0x00, 0x02, 0x10, 0xff];
+        header.extend([0xff; 8]);
+        header.extend([0x01, 0x10, 0x01, 0x01]);
+        assert!(matches!(
+            list(&seven_zip(&[], &header)),
+            Err(Error::SevenZip("invalid encoded header"))
+        ));
+
+        // Substream sizes that add up past `u64::MAX`.
+        let mut header = vec![
+            0x17, 0x07, 0x0b, 0x01, 0x00, 0x01, 0x01, 0x00, 0x0c, 0x05, 0x00, 0x08, 0x0d, 0x03,
+            0x09, 0xff,
+        ];
+        header.extend([0xff; 8]);
+        header.extend([0x01, 0x00, 0x00]);
+        assert!(matches!(
+            list(&seven_zip(&[], &header)),
+            Err(Error::SevenZip("invalid encoded header"))
+        ));
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::fs;
+
+use crate::{error::Error, Command};
+
+#[derive(Debug)]
+pub struct BmpFix {
+    file: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Header {
+    file_size: u32,
+    offset: u32,
+    width: i32,
+    height: i32,
+    bit_count: u16,
+    compression: u32,
+}
+
+impl Header {
+    fn from(data: &[u8]) -> Result<Self, Error> {
+        // BITMAPCOREHEADER stores 16-bit dimensions and is too rare to bother with.
+        if !data.starts_with(b"BM") || data.len() < 54 {
+            return Err(Error::BmpHeader);
+        }
+
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        if u32_at(14) < 40 {
+            return Err(Error::BmpHeader);
+        }
+        Ok(Self {
+            file_size: u32_at(2),
+            offset: u32_at(10),
+            width: u32_at(18) as i32,
+            height: u32_at(22) as i32,
+            bit_count: u16::from_le_bytes([data[28], data[29]]),
+            compression: u32_at(30),
+        })
+    }
+
+    fn stride(&self, width: u32) -> u64 {
+        (width as u64 * self.bit_count as u64).div_ceil(32) * 4
+    }
+
+    /// Returns the dimensions that fill `size` bytes of pixel data exactly, keeping either the
+    /// width or the height. A negative (top-down) height keeps its sign.
+    fn candidates(&self, size: u64) -> Vec<(i32, i32)> {
+        let mut candidates = Vec::new();
+
+        let stride = self.stride(self.width.unsigned_abs());
+        if stride != 0 && size.is_multiple_of(stride) {
+            let height = i32::try_from(size / stride).unwrap_or_default();
+            let height = if self.height < 0 { -height } else { height };
+            candidates.push((self.width, height));
+        }
+
+        let rows = self.height.unsigned_abs() as u64;
+        if rows != 0 && size.is_multiple_of(rows) && self.bit_count != 0 {
+            let stride = size / rows;
+            let width = u32::try_from(stride * 8 / self.bit_count as u64).unwrap_or_default();
+            if self.stride(width) == stride {
+                candidates.push((i32::try_from(width).unwrap_or_default(), self.height));
+            }
+        }
+
+        candidates.retain(|&(w, h)| w > 0 && h != 0 && (w, h) != (self.width, self.height));
+        candidates.dedup();
+        candidates
+    }
+}
+
+impl BmpFix {
+    pub fn new(file: String) -> Self {
+        Self { file }
+    }
+}
+
+#[async_trait]
+impl Command for BmpFix {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self { file } = *self;
+
+        let mut data = fs::read(&file).await?;
+        let header = Header::from(&data)?;
+        tracing::info!(?header, len = data.len());
+        if !matches!(header.compression, 0 | 3 | 6) {
+            return Err(anyhow::anyhow!(
+                "Unsupported BMP compression {}.",
+                header.compression
+            ));
+        }
+
+        // bfSize is the documented size, fall back to the real length when it was tampered too.
+        let mut sizes = vec![header.file_size as u64, data.len() as u64];
+        sizes.dedup();
+        let mut candidates = sizes
+            .into_iter()
+            .filter_map(|x| x.checked_sub(header.offset as u64))
+            .flat_map(|x| header.candidates(x))
+            .collect::<Vec<_>>();
+        candidates.dedup();
+        if candidates.is_empty() {
+            tracing::info!("Dimensions already match the pixel data.");
+            return Ok(());
+        }
+
+        let stem = PathBuf::from(&file)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap()
+            .to_owned();
+        for (i, (width, height)) in candidates.into_iter().enumerate() {
+            data[18..22].copy_from_slice(&width.to_le_bytes());
+            data[22..26].copy_from_slice(&height.to_le_bytes());
+
+            let path = match i {
+                0 => format!("{}-fixed.bmp", stem),
+                _ => format!("{}-fixed-{}x{}.bmp", stem, width, height),
+            };
+            fs::write(&path, &data).await?;
+            tracing::info!(
+                "Fixed bmp with width({}), height({}) saved as ({:?}).",
+                width,
+                height,
+                path
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Header;
+
+    fn bmp(width: i32, height: i32, bit_count: u16, size: u32) -> Vec<u8> {
+        let mut data = vec![0; 54];
+        data[0..2].copy_from_slice(b"BM");
+        data[2..6].copy_from_slice(&(54 + size).to_le_bytes());
+        data[10..14].copy_from_slice(&54_u32.to_le_bytes());
+        data[14..18].copy_from_slice(&40_u32.to_le_bytes());
+        data[18..22].copy_from_slice(&width.to_le_bytes());
+        data[22..26].copy_from_slice(&height.to_le_bytes());
+        data[28..30].copy_from_slice(&bit_count.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_candidates() {
+        let header = Header::from(&bmp(5, 2, 24, 16 * 7)).unwrap();
+        assert_eq!(header.file_size, 54 + 16 * 7);
+        assert_eq!(header.candidates(16 * 7), vec![(5, 7), (18, 2)]);
+
+        let header = Header::from(&bmp(5, -2, 24, 16 * 7)).unwrap();
+        assert_eq!(header.candidates(16 * 7), vec![(5, -7), (18, -2)]);
+
+        let header = Header::from(&bmp(5, 7, 24, 16 * 7)).unwrap();
+        assert!(header.candidates(16 * 7).is_empty());
+
+        assert!(Header::from(b"BM").is_err());
+    }
+}
@@ -18,13 +18,84 @@ pub enum CliCommand {
     PngCrc {
         #[arg(short = 'i', long = "in")]
         file: String,
+
+        #[arg(long = "fix-crc", group = "mode")]
+        fix_crc: bool,
+
+        #[arg(long = "from-idat", group = "mode")]
+        from_idat: bool,
+
+        #[arg(long, group = "mode")]
+        others: bool,
+
+        #[arg(long, group = "mode")]
+        carve: bool,
+
+        #[arg(long, group = "mode")]
+        repair: bool,
+
+        // The heights table takes tens of bytes per entry, so the search stops at 2^20.
+        #[arg(long, default_value_t = 8192, value_parser = clap::value_parser!(u32).range(1..=1 << 20))]
+        max: u32,
     },
-    ZipCrc {
+    PngCgbi {
+        #[arg(short = 'i', long = "in")]
+        file: String,
+    },
+    PngApng {
         #[arg(short = 'i', long = "in")]
         file: String,
+    },
+    PngIdat {
+        #[arg(short = 'i', long = "in")]
+        file: String,
+    },
+    JpegFix {
+        #[arg(short = 'i', long = "in")]
+        file: String,
+
+        #[arg(long)]
+        width: Option<u16>,
+
+        #[arg(long)]
+        height: Option<u16>,
+    },
+    BmpFix {
+        #[arg(short = 'i', long = "in")]
+        file: String,
+    },
+    GifFix {
+        #[arg(short = 'i', long = "in")]
+        file: String,
+    },
+    Crc {
+        #[arg(short = 'i', long = "in", required_unless_present = "target")]
+        file: Option<String>,
 
         #[arg(short, long)]
-        size: u64,
+        algorithm: Option<CrcAlgorithm>,
+
+        #[arg(short, long, value_parser = parse_hex)]
+        target: Option<u32>,
+
+        #[arg(short, long, conflicts_with_all = ["file", "mask"])]
+        size: Option<usize>,
+
+        #[arg(short, long, requires_all = ["file", "target"])]
+        offset: Option<usize>,
+
+        #[arg(short, long, conflicts_with = "file")]
+        charset: Option<String>,
+
+        #[arg(short, long, conflicts_with = "file")]
+        mask: Option<String>,
+    },
+    ZipCrc {
+        #[arg(short = 'i', long = "in")]
+        file: String,
+
+        #[arg(short = 's', long = "size", default_value_t = 6)]
+        max_size: u64,
 
         #[arg(
             short,
@@ -32,6 +103,58 @@ pub enum CliCommand {
             default_value = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~ \t\n\r\x0b\x0c"
         )]
         alphabet: String,
+
+        #[arg(short, long)]
+        charset: Option<String>,
+
+        #[arg(short, long = "mask")]
+        masks: Vec<String>,
+
+        #[arg(short, long = "template")]
+        templates: Vec<String>,
+
+        #[arg(short, long)]
+        pattern: Option<String>,
+    },
+    ZipKpa {
+        #[arg(short = 'i', long = "in")]
+        file: String,
+
+        #[arg(short, long)]
+        entry: Option<String>,
+
+        #[arg(short, long)]
+        plain: Option<String>,
+
+        #[arg(long = "plain-zip")]
+        plain_zip: Option<String>,
+
+        #[arg(short, long, default_value_t = 0)]
+        offset: usize,
+
+        #[arg(long)]
+        password: Option<String>,
+    },
+    ZipPass {
+        #[arg(short = 'i', long = "in")]
+        file: String,
+
+        #[arg(short, long)]
+        entry: Option<String>,
+
+        #[arg(short, long, conflicts_with = "mask")]
+        wordlist: Option<String>,
+
+        #[arg(short, long)]
+        mask: Option<String>,
+    },
+    ZipRepair {
+        #[arg(short = 'i', long = "in")]
+        file: String,
+    },
+    ZipPseudo {
+        #[arg(short = 'i', long = "in")]
+        file: String,
     },
     Base64Steg {
         #[arg(short = 'i', long = "in")]
@@ -113,6 +236,25 @@ pub enum CliCommand {
     },
 }
 
+#[derive(Clone, Debug, ValueEnum)]
+pub enum CrcAlgorithm {
+    Crc32,
+    Crc32c,
+    Crc32Bzip2,
+    Crc32Mpeg2,
+    Crc32Posix,
+    Crc16Arc,
+    Crc16Modbus,
+    Crc16Xmodem,
+    Crc16Kermit,
+    Crc16CcittFalse,
+}
+
+fn parse_hex(value: &str) -> Result<u32, std::num::ParseIntError> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    u32::from_str_radix(value.strip_prefix("0X").unwrap_or(value), 16)
+}
+
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, ValueEnum)]
 pub enum ImageStegOrder {
@@ -0,0 +1,639 @@
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use anyhow::Result;
+use tokio::{sync::Mutex, task};
+use tracing::instrument;
+
+use crate::{mask, rank};
+
+/// Each CRC being solved with a label and the preimages found so far.
+pub type SolutionMap = HashMap<u32, (String, Mutex<Vec<Vec<u8>>>)>;
+
+pub const CRC32: Engine = Model::CRC32.engine();
+
+/// Table driven CRC of any [`Model`], stepping its raw register forwards and backwards, which
+/// is all solving and forging need.
+#[derive(Clone, Debug)]
+pub struct Engine {
+    model: Model,
+    table: [u32; 256],
+    /// Table entries have distinct bytes where the register shifts out, so that byte alone
+    /// finds the index.
+    index: [u8; 256],
+    inverse: [u32; 256],
+}
+
+/// A CRC in the usual parameterized form, reflected models shift right with reversed polys.
+/// Widths are whole bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct Model {
+    width: u32,
+    poly: u32,
+    init: u32,
+    reflect: bool,
+    xorout: u32,
+}
+
+impl Engine {
+    const fn new(model: Model) -> Self {
+        assert!(model.width.is_multiple_of(8) && model.width >= 8 && model.width <= 32);
+        let mask = model.mask();
+        let shift = model.width - 8;
+        let poly = match model.reflect {
+            true => model.poly.reverse_bits() >> (32 - model.width),
+            false => model.poly,
+        };
+
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = match model.reflect {
+                true => i as u32,
+                false => (i as u32) << shift,
+            };
+            let mut k = 0;
+            while k < 8 {
+                crc = match (model.reflect, crc & 1 == 1, crc >> (model.width - 1) == 1) {
+                    (true, true, _) => (crc >> 1) ^ poly,
+                    (true, false, _) => crc >> 1,
+                    (false, _, true) => ((crc << 1) ^ poly) & mask,
+                    (false, _, false) => (crc << 1) & mask,
+                };
+                k += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+
+        // Reflected registers shift their top byte out of the table entry, the others their low
+        // byte, and undoing a step puts the index back at the other end.
+        let (mut index, mut inverse) = ([0; 256], [0; 256]);
+        let mut i = 0;
+        while i < 256 {
+            let (out, back) = match model.reflect {
+                true => (table[i] >> shift, ((table[i] << 8) & mask) ^ i as u32),
+                false => (table[i] & 0xff, (table[i] >> 8) ^ ((i as u32) << shift)),
+            };
+            index[out as usize] = i as u8;
+            inverse[out as usize] = back;
+            i += 1;
+        }
+
+        Self {
+            model,
+            table,
+            index,
+            inverse,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.model.width
+    }
+
+    /// Bytes a forge can fix, one register's worth.
+    pub fn bytes(&self) -> usize {
+        self.model.width as usize / 8
+    }
+
+    /// The raw register before any data, the reflected `init` for reflected models.
+    pub fn init(&self) -> u32 {
+        match self.model.reflect {
+            true => self.model.init.reverse_bits() >> (32 - self.model.width),
+            false => self.model.init,
+        }
+    }
+
+    /// The CRC of a final raw register, and as the xor undoes itself, the final register of
+    /// a CRC.
+    pub fn finalize(&self, crc: u32) -> u32 {
+        crc ^ self.model.xorout
+    }
+
+    /// Steps the raw register (no init or xorout) over one byte.
+    pub fn update(&self, crc: u32, byte: u8) -> u32 {
+        let shift = self.model.width - 8;
+        match self.model.reflect {
+            true => (crc >> 8) ^ self.table[((crc ^ byte as u32) & 0xff) as usize],
+            false => {
+                let i = ((crc >> shift) ^ byte as u32) & 0xff;
+                ((crc << 8) & self.model.mask()) ^ self.table[i as usize]
+            }
+        }
+    }
+
+    /// Undoes [`Engine::update`], recovering the register before `byte` was processed.
+    pub fn update_backward(&self, crc: u32, byte: u8) -> u32 {
+        let shift = self.model.width - 8;
+        match self.model.reflect {
+            true => {
+                ((crc << 8) & self.model.mask())
+                    ^ self.inverse[(crc >> shift) as usize]
+                    ^ byte as u32
+            }
+            false => (crc >> 8) ^ self.inverse[(crc & 0xff) as usize] ^ ((byte as u32) << shift),
+        }
+    }
+
+    pub fn update_slice(&self, crc: u32, data: &[u8]) -> u32 {
+        data.iter().fold(crc, |crc, &x| self.update(crc, x))
+    }
+
+    pub fn checksum(&self, data: &[u8]) -> u32 {
+        self.finalize(self.update_slice(self.init(), data))
+    }
+
+    /// Finds the `len` (at most [`Engine::bytes`]) bytes that take the raw register from
+    /// `from` to `to`. A full register of bytes always has exactly one solution, fewer bytes
+    /// have at most one.
+    pub fn forge(&self, from: u32, to: u32, len: usize) -> Option<Vec<u8>> {
+        if len > self.bytes() {
+            return None;
+        }
+
+        // Walk backwards, keeping the table index of each step where the byte went in.
+        let (mask, shift) = (self.model.mask(), self.model.width - 8);
+        let mut crc = to;
+        for _ in 0..len {
+            crc = match self.model.reflect {
+                true => {
+                    let i = self.index[(crc >> shift) as usize];
+                    (((crc ^ self.table[i as usize]) << 8) & mask) | i as u32
+                }
+                false => {
+                    let i = self.index[(crc & 0xff) as usize];
+                    ((crc ^ self.table[i as usize]) >> 8) | ((i as u32) << shift)
+                }
+            };
+        }
+
+        // The bytes sit at the end they went in, the first one furthest inside.
+        let diff = crc ^ from;
+        let bits = 8 * len as u32;
+        let (bytes, rest) = match self.model.reflect {
+            true => (
+                (0..len).map(|k| (diff >> (8 * k)) as u8).collect(),
+                diff.checked_shr(bits).unwrap_or_default(),
+            ),
+            false => (
+                (0..len)
+                    .map(|k| (diff >> (shift - 8 * k as u32)) as u8)
+                    .collect(),
+                diff & mask.checked_shr(bits).unwrap_or_default(),
+            ),
+        };
+        match rest {
+            0 => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Finds the [`Engine::bytes`] bytes at `offset` that give `data` the CRC `target`.
+    pub fn patch(&self, data: &[u8], offset: usize, target: u32) -> Vec<u8> {
+        let len = self.bytes();
+        let from = self.update_slice(self.init(), &data[..offset]);
+        let to = data[offset + len..]
+            .iter()
+            .rev()
+            .fold(self.finalize(target), |crc, &x| {
+                self.update_backward(crc, x)
+            });
+
+        self.forge(from, to, len).unwrap()
+    }
+}
+
+/// [`Engine::update`] of the zip and png CRC32.
+pub fn update(crc: u32, byte: u8) -> u32 {
+    CRC32.update(crc, byte)
+}
+
+/// [`Engine::update_backward`] of the zip and png CRC32.
+pub fn update_backward(crc: u32, byte: u8) -> u32 {
+    CRC32.update_backward(crc, byte)
+}
+
+impl Model {
+    pub const CRC32: Self = Self::new(32, 0x04c11db7, !0, true, !0);
+    pub const CRC32C: Self = Self::new(32, 0x1edc6f41, !0, true, !0);
+    pub const CRC32_BZIP2: Self = Self::new(32, 0x04c11db7, !0, false, !0);
+    pub const CRC32_MPEG2: Self = Self::new(32, 0x04c11db7, !0, false, 0);
+    pub const CRC32_POSIX: Self = Self::new(32, 0x04c11db7, 0, false, !0);
+    pub const CRC16_ARC: Self = Self::new(16, 0x8005, 0, true, 0);
+    pub const CRC16_MODBUS: Self = Self::new(16, 0x8005, 0xffff, true, 0);
+    pub const CRC16_XMODEM: Self = Self::new(16, 0x1021, 0, false, 0);
+    pub const CRC16_KERMIT: Self = Self::new(16, 0x1021, 0, true, 0);
+    pub const CRC16_CCITT_FALSE: Self = Self::new(16, 0x1021, 0xffff, false, 0);
+
+    const fn new(width: u32, poly: u32, init: u32, reflect: bool, xorout: u32) -> Self {
+        Self {
+            width,
+            poly,
+            init,
+            reflect,
+            xorout,
+        }
+    }
+
+    const fn mask(&self) -> u32 {
+        u32::MAX >> (32 - self.width)
+    }
+
+    /// Builds the tables for computing, solving and forging this model.
+    pub const fn engine(self) -> Engine {
+        Engine::new(self)
+    }
+}
+
+/// A preimage search over the bytes allowed at each position, for every CRC at once.
+#[derive(Debug)]
+pub struct Context {
+    /// Bytes allowed at each position.
+    pub positions: Vec<Vec<u8>>,
+    pub allowed: Vec<[bool; 256]>,
+    /// Enumerated positions, followed by a forged window of up to a register of bytes and a
+    /// known tail.
+    pub head: usize,
+    pub window: usize,
+    /// The known bytes after the window.
+    pub tail: Vec<u8>,
+    /// Each CRC with the raw register it needs right after the window.
+    pub targets: Vec<(u32, u32)>,
+    pub crc2pts: SolutionMap,
+    pub engine: Engine,
+}
+
+impl Context {
+    pub fn new(positions: Vec<Vec<u8>>, crc2pts: SolutionMap, engine: Engine) -> Self {
+        let allowed = positions
+            .iter()
+            .map(|x| {
+                let mut allowed = [false; 256];
+                x.iter().for_each(|&b| allowed[b as usize] = true);
+                allowed
+            })
+            .collect();
+        let size = positions.len();
+        let known = positions.iter().rev().take_while(|x| x.len() == 1).count();
+        let window = (size - known).min(engine.bytes());
+        let tail = positions[size - known..]
+            .iter()
+            .map(|x| x[0])
+            .collect::<Vec<_>>();
+        // Walking the register back over the known tail only depends on the CRC, so it is done
+        // once here instead of for every prefix.
+        let targets = crc2pts
+            .keys()
+            .map(|&crc| {
+                let to = tail.iter().rev().fold(engine.finalize(crc), |crc, &x| {
+                    engine.update_backward(crc, x)
+                });
+                (crc, to)
+            })
+            .collect();
+        Self {
+            positions,
+            allowed,
+            head: size - known - window,
+            window,
+            tail,
+            targets,
+            crc2pts,
+            engine,
+        }
+    }
+
+    /// Forges the window after `prefix`, which leaves the raw register at `from`, so the
+    /// register reaches `to` before the known tail.
+    fn complete(&self, prefix: &[u8], from: u32, to: u32) -> Option<Vec<u8>> {
+        let window = self.engine.forge(from, to, self.window)?;
+        window
+            .iter()
+            .zip(&self.allowed[self.head..])
+            .all(|(&x, allowed)| allowed[x as usize])
+            .then(|| [prefix, &window, &self.tail].concat())
+    }
+}
+
+/// Every plaintext with bytes from `positions` whose `engine` CRC is `target`.
+pub async fn preimages(
+    engine: Engine,
+    positions: Vec<Vec<u8>>,
+    target: u32,
+) -> Result<Vec<Vec<u8>>> {
+    let mut crc2pts = SolutionMap::new();
+    crc2pts.insert(target, (format!("{:#010x}", target), Mutex::default()));
+    let ctx = solve(Context::new(positions, crc2pts, engine)).await?;
+
+    Ok(ctx
+        .crc2pts
+        .into_values()
+        .flat_map(|x| x.1.into_inner())
+        .collect())
+}
+
+/// Finds the preimages of every CRC in `ctx`, enumerating on worker threads.
+pub async fn solve(mut ctx: Context) -> Result<Context> {
+    if ctx.head == 0 {
+        invert(&mut ctx);
+        return Ok(ctx);
+    }
+
+    let (mut ctx, found) = task::spawn_blocking(move || {
+        let found = search(&ctx);
+        (ctx, found)
+    })
+    .await?;
+    for (crc, pt) in found {
+        ctx.crc2pts.get_mut(&crc).unwrap().1.get_mut().push(pt);
+    }
+
+    Ok(ctx)
+}
+
+/// Enumerates the completions of `prefix` up to the forged window, so a 6-byte entry costs
+/// two levels of search and known bytes cost nothing.
+#[instrument(skip(ctx))]
+fn brute(prefix: Vec<u8>, ctx: &Context) -> Vec<(u32, Vec<u8>)> {
+    let mut found = Vec::new();
+    let from = ctx.engine.update_slice(ctx.engine.init(), &prefix);
+    let step = |crc, b| ctx.engine.update(crc, b);
+    let rest = &ctx.positions[prefix.len()..ctx.head];
+    mask::walk(prefix, from, rest, step, |prefix, from| {
+        for &(crc, to) in &ctx.targets {
+            if let Some(pt) = ctx.complete(prefix, from, to) {
+                tracing::trace!(pt = rank::show(&pt));
+                found.push((crc, pt));
+            }
+        }
+        false
+    });
+
+    found
+}
+
+/// Splits the enumerated positions into work items over their leading product, so a known
+/// prefix does not leave a single item, and hands them out to a thread per core.
+fn search(ctx: &Context) -> Vec<(u32, Vec<u8>)> {
+    let positions = &ctx.positions[..ctx.head];
+    let (split, items) = mask::split(positions);
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let next = AtomicUsize::new(0);
+    thread::scope(|s| {
+        let handles = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut found = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= items {
+                            break;
+                        }
+                        let prefix = mask::nth(&positions[..split], i);
+                        found.extend(brute(prefix, ctx));
+                    }
+                    found
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|x| x.join().expect("Failed to join brute thread."))
+            .collect()
+    })
+}
+
+/// Without unknown bytes before the window there is nothing to search, each CRC has at most
+/// one preimage.
+fn invert(ctx: &mut Context) {
+    let solved = ctx
+        .targets
+        .iter()
+        .map(|&(crc, to)| (crc, ctx.complete(&[], ctx.engine.init(), to)))
+        .collect::<Vec<_>>();
+    for (crc, pt) in solved {
+        ctx.crc2pts.get_mut(&crc).unwrap().1.get_mut().extend(pt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use tokio::sync::Mutex;
+
+    use super::{
+        brute, invert, preimages, solve, update, update_backward, Context, Model, SolutionMap,
+        CRC32,
+    };
+    use crate::mask;
+
+    #[test]
+    fn test_update() {
+        let crc = b"flag".iter().fold(!0, |crc, &x| update(crc, x));
+        assert_eq!(!crc, crc32fast::hash(b"flag"));
+
+        let crc = b"flag"
+            .iter()
+            .rev()
+            .fold(crc, |crc, &x| update_backward(crc, x));
+        assert_eq!(crc, !0);
+    }
+
+    /// Forging from the initial register finds the message with a given CRC32.
+    #[test]
+    fn test_invert() {
+        let invert = |crc: u32, len| CRC32.forge(!0, !crc, len);
+        for pt in [&b"f"[..], b"fl", b"fla", b"flag", b"\0\0\0\0", b"\xff\xfe"] {
+            assert_eq!(invert(crc32fast::hash(pt), pt.len()).unwrap(), pt);
+        }
+
+        for x in 0..=u8::MAX {
+            let crc = crc32fast::hash(&[x]) ^ 1;
+            let exists = (0..=u8::MAX).any(|y| crc32fast::hash(&[y]) == crc);
+            assert_eq!(invert(crc, 1).is_some(), exists);
+        }
+        assert_eq!(invert(0, 0), Some(Vec::new()));
+        assert!(invert(0, 5).is_none());
+    }
+
+    #[test]
+    fn test_forge() {
+        let from = !crc32fast::hash(b"prefix");
+        let to = !crc32fast::hash(b"prefix1234");
+        assert_eq!(CRC32.forge(from, to, 4).unwrap(), b"1234");
+    }
+
+    #[test]
+    fn test_checksum() {
+        let checks = [
+            (Model::CRC32, 0xcbf43926),
+            (Model::CRC32C, 0xe3069283),
+            (Model::CRC32_BZIP2, 0xfc891918),
+            (Model::CRC32_MPEG2, 0x0376e6e7),
+            (Model::CRC32_POSIX, 0x765e7680),
+            (Model::CRC16_ARC, 0xbb3d),
+            (Model::CRC16_MODBUS, 0x4b37),
+            (Model::CRC16_XMODEM, 0x31c3),
+            (Model::CRC16_KERMIT, 0x2189),
+            (Model::CRC16_CCITT_FALSE, 0x29b1),
+        ];
+        for (model, check) in checks {
+            assert_eq!(model.engine().checksum(b"123456789"), check, "{:?}", model);
+        }
+        assert_eq!(CRC32.checksum(b"flag"), crc32fast::hash(b"flag"));
+    }
+
+    /// Forging and patching hold for CRC16s and CRC32s of either bit order, whatever their
+    /// init and xorout.
+    #[test]
+    fn test_forge_models() {
+        let models = [
+            Model::CRC16_ARC,
+            Model::CRC16_XMODEM,
+            Model::CRC16_CCITT_FALSE,
+            Model::CRC32_BZIP2,
+            Model::CRC32_MPEG2,
+            Model::CRC32_POSIX,
+        ];
+        for model in models {
+            let engine = model.engine();
+            let len = engine.bytes();
+            let crc = engine.update_slice(engine.init(), b"flag{");
+            let back = b"flag{"
+                .iter()
+                .rev()
+                .fold(crc, |crc, &x| engine.update_backward(crc, x));
+            assert_eq!(back, engine.init(), "{:?}", model);
+
+            let to = engine.update_slice(crc, &b"1234"[..len]);
+            assert_eq!(engine.forge(crc, to, len).unwrap(), &b"1234"[..len]);
+
+            let target = 0xdeadbeef >> (32 - engine.width());
+            for offset in [0, 3, 8] {
+                let mut data = b"flag{forged}".to_vec();
+                let patch = engine.patch(&data, offset, target);
+                data[offset..offset + len].copy_from_slice(&patch);
+                assert_eq!(engine.checksum(&data), target, "{:?}", model);
+            }
+        }
+    }
+
+    #[test]
+    fn test_brute() {
+        let flag = b"flags".to_vec();
+        let crc = crc32fast::hash(&flag);
+        let mut crc2pts = SolutionMap::new();
+        crc2pts.insert(crc, ("demo.txt".to_owned(), Mutex::default()));
+        let alphabet = (b'a'..=b'y').collect();
+        let ctx = Context::new(vec![alphabet; 5], crc2pts, CRC32);
+        let found = brute(b"f".to_vec(), &ctx);
+        assert_eq!(found, vec![(crc, flag)]);
+    }
+
+    #[test]
+    fn test_brute_six() {
+        let mut crc2pts = SolutionMap::new();
+        for pt in ["flag{}", "fl4g!?"] {
+            crc2pts.insert(
+                crc32fast::hash(pt.as_bytes()),
+                (pt.to_owned(), Mutex::default()),
+            );
+        }
+        let alphabet = (b' '..=b'~').collect::<Vec<_>>();
+        let ctx = Context::new(vec![alphabet; 6], crc2pts, CRC32);
+        let found = brute(b"f".to_vec(), &ctx);
+        for (crc, (name, _)) in &ctx.crc2pts {
+            assert!(found.contains(&(*crc, name.clone().into_bytes())));
+        }
+        assert!(found.iter().all(|(crc, x)| crc32fast::hash(x) == *crc));
+    }
+
+    #[test]
+    fn test_invert_window() {
+        let mut crc2pts = SolutionMap::new();
+        for pt in ["fla", "g}", "\x01\x02\x03"] {
+            crc2pts.insert(
+                crc32fast::hash(pt.as_bytes()),
+                (pt.to_owned(), Mutex::default()),
+            );
+        }
+        let mut ctx = Context::new(vec![(b'a'..=b'z').collect(); 3], crc2pts, CRC32);
+        invert(&mut ctx);
+
+        let solved = ctx
+            .crc2pts
+            .into_values()
+            .map(|(name, pts)| (name, pts.into_inner()))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(solved["fla"], vec![b"fla".to_vec()]);
+        assert!(solved["g}"].is_empty());
+        assert!(solved["\x01\x02\x03"].is_empty());
+
+        let mut crc2pts = SolutionMap::new();
+        crc2pts.insert(
+            crc32fast::hash(b"\x00\xff\x80"),
+            ("bin".to_owned(), Mutex::default()),
+        );
+        let mut ctx = Context::new(
+            vec![mask::parse_charset("00-ff").unwrap(); 3],
+            crc2pts,
+            CRC32,
+        );
+        invert(&mut ctx);
+        let (_, (_, pts)) = ctx.crc2pts.into_iter().next().unwrap();
+        assert_eq!(pts.into_inner(), vec![b"\x00\xff\x80".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_mask() {
+        let any = mask::preset("printable").unwrap();
+        for (pt, mask) in [("flag{a1b2c3}", "flag{?l?d?l?d??}"), ("ab!}", "a??}")] {
+            let mut crc2pts = SolutionMap::new();
+            crc2pts.insert(
+                crc32fast::hash(pt.as_bytes()),
+                (pt.to_owned(), Mutex::default()),
+            );
+            let positions = mask::parse(mask, Some(&any)).unwrap();
+            let ctx = solve(Context::new(positions, crc2pts, CRC32))
+                .await
+                .unwrap();
+
+            let (_, (_, pts)) = ctx.crc2pts.into_iter().next().unwrap();
+            let pts = pts.into_inner();
+            assert!(pts.contains(&pt.as_bytes().to_vec()));
+            assert!(pts
+                .iter()
+                .all(|x| x.ends_with(b"}") && x[0] == pt.as_bytes()[0]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preimages() {
+        let positions = vec![(b'a'..=b'z').collect(); 6];
+        let engine = Model::CRC32C.engine();
+        let pts = preimages(engine.clone(), positions, engine.checksum(b"secret"))
+            .await
+            .unwrap();
+        assert!(pts.iter().any(|x| x == b"secret"));
+        assert!(pts
+            .iter()
+            .all(|x| engine.checksum(x) == engine.checksum(b"secret")));
+
+        let engine = Model::CRC16_XMODEM.engine();
+        let positions = vec![(b'a'..=b'z').collect(); 3];
+        let pts = preimages(engine.clone(), positions, engine.checksum(b"key"))
+            .await
+            .unwrap();
+        assert!(pts.iter().any(|x| x == b"key"));
+        assert!(pts
+            .iter()
+            .all(|x| engine.checksum(x) == engine.checksum(b"key")));
+    }
+}
@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use tokio::fs;
+
+use crate::{
+    cli::CrcAlgorithm,
+    crc::{self, Engine, Model},
+    error::Error,
+    mask, rank, Command,
+};
+
+#[derive(Debug)]
+pub struct CrcUtil {
+    file: Option<String>,
+    algorithm: Option<CrcAlgorithm>,
+    target: Option<u32>,
+    size: Option<usize>,
+    offset: Option<usize>,
+    charset: Option<String>,
+    mask: Option<String>,
+}
+
+impl From<CrcAlgorithm> for Model {
+    fn from(value: CrcAlgorithm) -> Self {
+        match value {
+            CrcAlgorithm::Crc32 => Model::CRC32,
+            CrcAlgorithm::Crc32c => Model::CRC32C,
+            CrcAlgorithm::Crc32Bzip2 => Model::CRC32_BZIP2,
+            CrcAlgorithm::Crc32Mpeg2 => Model::CRC32_MPEG2,
+            CrcAlgorithm::Crc32Posix => Model::CRC32_POSIX,
+            CrcAlgorithm::Crc16Arc => Model::CRC16_ARC,
+            CrcAlgorithm::Crc16Modbus => Model::CRC16_MODBUS,
+            CrcAlgorithm::Crc16Xmodem => Model::CRC16_XMODEM,
+            CrcAlgorithm::Crc16Kermit => Model::CRC16_KERMIT,
+            CrcAlgorithm::Crc16CcittFalse => Model::CRC16_CCITT_FALSE,
+        }
+    }
+}
+
+impl CrcUtil {
+    pub fn new(
+        file: Option<String>,
+        algorithm: Option<CrcAlgorithm>,
+        target: Option<u32>,
+        size: Option<usize>,
+        offset: Option<usize>,
+        charset: Option<String>,
+        mask: Option<String>,
+    ) -> Self {
+        Self {
+            file,
+            algorithm,
+            target,
+            size,
+            offset,
+            charset,
+            mask,
+        }
+    }
+
+    fn name(algorithm: &CrcAlgorithm) -> String {
+        algorithm
+            .to_possible_value()
+            .map(|x| x.get_name().to_owned())
+            .unwrap_or_default()
+    }
+
+    /// The engine of `algorithm`, provided `target` fits its width.
+    fn engine(algorithm: &CrcAlgorithm, target: u32) -> Result<Engine, Error> {
+        let engine = Model::from(algorithm.clone()).engine();
+        match target.checked_shr(engine.width()).unwrap_or_default() {
+            0 => Ok(engine),
+            _ => Err(Error::CrcTarget(target, Self::name(algorithm))),
+        }
+    }
+
+    fn compute(data: &[u8], algorithms: &[CrcAlgorithm]) {
+        for algorithm in algorithms {
+            let engine = Model::from(algorithm.clone()).engine();
+            let digits = engine.width() as usize / 4;
+            let crc = engine.checksum(data);
+            tracing::info!(
+                "{} is ({:#0w$x}).",
+                Self::name(algorithm),
+                crc,
+                w = digits + 2
+            );
+        }
+    }
+
+    /// Patches a register of bytes at `offset`, appending them when `offset` is the end of
+    /// `data`.
+    fn splice(
+        engine: &Engine,
+        mut data: Vec<u8>,
+        offset: usize,
+        target: u32,
+    ) -> Result<Vec<u8>, Error> {
+        if offset > data.len() {
+            return Err(Error::CrcOffset(offset));
+        }
+        let len = engine.bytes();
+        if offset + len > data.len() {
+            data.resize(offset + len, 0);
+        }
+
+        let patch = engine.patch(&data, offset, target);
+        tracing::info!(
+            "Patched ({}) at offset ({:#x}).",
+            hex::encode(&patch),
+            offset
+        );
+        data[offset..offset + len].copy_from_slice(&patch);
+
+        Ok(data)
+    }
+
+    async fn solve(engine: Engine, positions: Vec<Vec<u8>>, target: u32) -> Result<()> {
+        let pts = crc::preimages(engine, positions, target).await?;
+        if pts.is_empty() {
+            return Err(anyhow::anyhow!("No preimages of {:#x} found.", target));
+        }
+
+        tracing::info!("Found {} preimages of ({:#x}).", pts.len(), target);
+        let ranked = rank::join(&[pts], None);
+        for (i, (score, pt)) in ranked.iter().enumerate() {
+            match i {
+                0..5 => tracing::info!("rank={}, score={:.2}, pt={:?}", i, score, rank::show(pt)),
+                _ => tracing::debug!("rank={}, score={:.2}, pt={:?}", i, score, rank::show(pt)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Command for CrcUtil {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self {
+            file,
+            algorithm,
+            target,
+            size,
+            offset,
+            charset,
+            mask,
+        } = *self;
+
+        match (file, target) {
+            (Some(file), None) => {
+                let data = fs::read(&file).await?;
+                match algorithm {
+                    Some(algorithm) => Self::compute(&data, &[algorithm]),
+                    None => Self::compute(&data, CrcAlgorithm::value_variants()),
+                }
+            }
+            (Some(file), Some(target)) => {
+                let engine = Self::engine(&algorithm.unwrap_or(CrcAlgorithm::Crc32), target)?;
+                let data = fs::read(&file).await?;
+                let offset = offset.unwrap_or(data.len());
+                let data = Self::splice(&engine, data, offset, target)?;
+
+                let path = Path::new(&file);
+                let stem = path.file_stem().and_then(|x| x.to_str()).unwrap();
+                let path = match path.extension().and_then(|x| x.to_str()) {
+                    Some(ext) => PathBuf::from(format!("{}-forged.{}", stem, ext)),
+                    None => PathBuf::from(format!("{}-forged", stem)),
+                };
+                fs::write(&path, data).await?;
+                tracing::info!("Forged file saved as ({:?}).", path);
+            }
+            (None, Some(target)) => {
+                let engine = Self::engine(&algorithm.unwrap_or(CrcAlgorithm::Crc32), target)?;
+                let alphabet = mask::parse_charset(charset.as_deref().unwrap_or("printable"))?;
+                let positions = match (mask, size) {
+                    (Some(mask), _) => mask::parse(&mask, Some(&alphabet))?,
+                    (None, Some(size)) => vec![alphabet; size],
+                    (None, None) => {
+                        return Err(anyhow::anyhow!("Expected --size or --mask to solve for."))
+                    }
+                };
+                Self::solve(engine, positions, target).await?;
+            }
+            (None, None) => return Err(anyhow::anyhow!("Expected --in or --target.")),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CrcUtil;
+    use crate::{
+        cli::CrcAlgorithm,
+        crc::{Model, CRC32},
+        error::Error,
+    };
+
+    #[test]
+    fn test_splice() {
+        let crc32c = Model::CRC32C.engine();
+        for (engine, offset) in [(&CRC32, 0), (&crc32c, 5), (&CRC32, 10)] {
+            let data =
+                CrcUtil::splice(engine, b"flag{crc32}".to_vec(), offset, 0xdeadbeef).unwrap();
+            assert_eq!(data.len(), 11.max(offset + 4));
+            assert_eq!(engine.checksum(&data), 0xdeadbeef);
+        }
+
+        let engine = Model::CRC16_MODBUS.engine();
+        let data = CrcUtil::splice(&engine, b"flag{crc16}".to_vec(), 10, 0xbeef).unwrap();
+        assert_eq!(data.len(), 12);
+        assert_eq!(engine.checksum(&data), 0xbeef);
+        assert!(matches!(
+            CrcUtil::engine(&CrcAlgorithm::Crc16Xmodem, 0x10000),
+            Err(Error::CrcTarget(0x10000, _))
+        ));
+        assert!(matches!(
+            CrcUtil::splice(&CRC32, b"flag".to_vec(), 5, 0),
+            Err(Error::CrcOffset(5))
+        ));
+    }
+}
@@ -2,8 +2,44 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("the Arc has not exactly one strong reference")]
-    ArcIntoInner,
     #[error("process exit status is not 0: {0}")]
     Process(String),
+    #[error("invalid png signature")]
+    PngSignature,
+    #[error("png has no valid IHDR chunk")]
+    PngIhdr,
+    #[error("png image data is {0} bytes, expected {1} bytes")]
+    PngIdat(usize, usize),
+    #[error("png row {0} has invalid filter type {1}")]
+    PngFilter(usize, u8),
+    #[error("invalid jpeg marker at offset {0:#x}")]
+    JpegMarker(usize),
+    #[error("jpeg has no SOF segment")]
+    JpegSof,
+    #[error("invalid bmp header")]
+    BmpHeader,
+    #[error("invalid gif block at offset {0:#x}")]
+    GifBlock(usize),
+    #[error("need at least {0} bytes of known plaintext, got {1}")]
+    ZipPlaintext(usize, usize),
+    #[error("invalid mask placeholder at offset {0}")]
+    Mask(usize),
+    #[error("zip entry {0} has data past the end of the archive")]
+    ZipData(String),
+    #[error("no zip local file header found")]
+    ZipLocal,
+    #[error("{0} needs zip64, which repair does not write")]
+    Zip64(String),
+    #[error("invalid charset item {0:?}")]
+    Charset(String),
+    #[error("corrupted lzma stream")]
+    Lzma,
+    #[error("invalid rar header at offset {0:#x}")]
+    RarHeader(usize),
+    #[error("unsupported or invalid 7z archive: {0}")]
+    SevenZip(&'static str),
+    #[error("target {0:#x} does not fit in {1}")]
+    CrcTarget(u32, String),
+    #[error("offset {0:#x} is past the end of the file")]
+    CrcOffset(usize),
 }
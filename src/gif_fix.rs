@@ -0,0 +1,284 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::fs;
+
+use crate::{error::Error, Command};
+
+#[derive(Debug)]
+pub struct GifFix {
+    file: String,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Image {
+    offset: usize,
+    left: u16,
+    top: u16,
+    width: u16,
+    height: u16,
+    pixels: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Gif {
+    width: u16,
+    height: u16,
+    images: Vec<Image>,
+}
+
+impl Gif {
+    fn parse(data: &[u8]) -> Result<Self, Error> {
+        if !(data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")) || data.len() < 13 {
+            return Err(Error::GifBlock(0));
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let mut gif = Self {
+            width: u16_at(6),
+            height: u16_at(8),
+            images: Vec::new(),
+        };
+        let mut pos = 13 + Self::color_table(data[10]);
+        loop {
+            match data.get(pos) {
+                Some(0x21) => {
+                    pos = Self::sub_blocks(data, pos + 2)
+                        .ok_or(Error::GifBlock(pos))?
+                        .1
+                }
+                Some(0x2c) => {
+                    let descriptor = data.get(pos..pos + 10).ok_or(Error::GifBlock(pos))?;
+                    let table = pos + 10 + Self::color_table(descriptor[9]);
+                    let min_code_size = *data.get(table).ok_or(Error::GifBlock(pos))?;
+                    let (lzw, end) =
+                        Self::sub_blocks(data, table + 1).ok_or(Error::GifBlock(pos))?;
+                    gif.images.push(Image {
+                        offset: pos,
+                        left: u16_at(pos + 1),
+                        top: u16_at(pos + 3),
+                        width: u16_at(pos + 5),
+                        height: u16_at(pos + 7),
+                        pixels: Self::decoded_len(min_code_size, &lzw),
+                    });
+                    pos = end;
+                }
+                Some(0x3b) => break,
+                None => {
+                    tracing::warn!("Missing trailer, gif is truncated.");
+                    break;
+                }
+                Some(_) => return Err(Error::GifBlock(pos)),
+            }
+        }
+
+        Ok(gif)
+    }
+
+    fn color_table(packed: u8) -> usize {
+        match packed & 0x80 {
+            0 => 0,
+            _ => 3 << ((packed & 0x07) + 1),
+        }
+    }
+
+    fn sub_blocks(data: &[u8], mut pos: usize) -> Option<(Vec<u8>, usize)> {
+        let mut blocks = Vec::new();
+        loop {
+            let size = *data.get(pos)? as usize;
+            if size == 0 {
+                return Some((blocks, pos + 1));
+            }
+            blocks.extend_from_slice(data.get(pos + 1..pos + 1 + size)?);
+            pos += 1 + size;
+        }
+    }
+
+    /// Runs the LZW decoder only far enough to count the pixels it would output.
+    fn decoded_len(min_code_size: u8, data: &[u8]) -> usize {
+        if !(1..=11).contains(&min_code_size) {
+            return 0;
+        }
+
+        let clear = 1 << min_code_size;
+        let mut lengths = vec![1; clear];
+        lengths.extend([0, 0]);
+        let mut size = min_code_size + 1;
+        let mut prev: Option<usize> = None;
+        let (mut acc, mut bits, mut total) = (0_u32, 0, 0);
+        for &byte in data {
+            acc |= (byte as u32) << bits;
+            bits += 8;
+            while bits >= size {
+                let code = (acc & ((1 << size) - 1)) as usize;
+                acc >>= size;
+                bits -= size;
+
+                if code == clear {
+                    lengths.truncate(clear + 2);
+                    size = min_code_size + 1;
+                    prev = None;
+                    continue;
+                }
+                if code == clear + 1 {
+                    return total;
+                }
+
+                let len = match (lengths.get(code), prev) {
+                    (Some(&len), _) => len,
+                    (None, Some(prev)) if code == lengths.len() => lengths[prev] + 1,
+                    _ => return total,
+                };
+                if let Some(prev) = prev.filter(|_| lengths.len() < 4096) {
+                    lengths.push(lengths[prev] + 1);
+                }
+                if lengths.len() == 1 << size && size < 12 {
+                    size += 1;
+                }
+                total += len;
+                prev = Some(code);
+            }
+        }
+
+        total
+    }
+
+    /// Patches image heights that disagree with their pixel count, then grows the logical
+    /// screen to cover every image.
+    fn fix(&self, data: &mut [u8]) -> bool {
+        let mut changed = false;
+        let (mut width, mut height) = (self.width, self.height);
+        for image in &self.images {
+            let mut image_height = image.height;
+            let expected = image.width as usize * image.height as usize;
+            if image.pixels != 0 && image.pixels != expected {
+                match u16::try_from(image.pixels / image.width.max(1) as usize) {
+                    Ok(x) if image.pixels % image.width.max(1) as usize == 0 => {
+                        tracing::info!(
+                            "Image at offset({:#x}) decodes {} pixels, height({}) -> height({}).",
+                            image.offset,
+                            image.pixels,
+                            image.height,
+                            x
+                        );
+                        data[image.offset + 7..image.offset + 9].copy_from_slice(&x.to_le_bytes());
+                        image_height = x;
+                        changed = true;
+                    }
+                    _ => tracing::warn!(
+                        "Image at offset({:#x}) decodes {} pixels, not a multiple of width({}).",
+                        image.offset,
+                        image.pixels,
+                        image.width
+                    ),
+                }
+            }
+
+            width = width.max(image.left.saturating_add(image.width));
+            height = height.max(image.top.saturating_add(image_height));
+        }
+
+        if (width, height) != (self.width, self.height) {
+            tracing::info!(
+                "Logical screen ({}x{}) -> ({}x{}).",
+                self.width,
+                self.height,
+                width,
+                height
+            );
+            data[6..8].copy_from_slice(&width.to_le_bytes());
+            data[8..10].copy_from_slice(&height.to_le_bytes());
+            changed = true;
+        }
+
+        changed
+    }
+}
+
+impl GifFix {
+    pub fn new(file: String) -> Self {
+        Self { file }
+    }
+}
+
+#[async_trait]
+impl Command for GifFix {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self { file } = *self;
+
+        let mut data = fs::read(&file).await?;
+        let gif = Gif::parse(&data)?;
+        tracing::info!(
+            "Read gif with logical screen ({}x{}) and {} images.",
+            gif.width,
+            gif.height,
+            gif.images.len()
+        );
+        for image in &gif.images {
+            tracing::debug!(?image);
+        }
+
+        if !gif.fix(&mut data) {
+            tracing::info!("Dimensions already match the image data.");
+            return Ok(());
+        }
+
+        let path = PathBuf::from(file)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .map(|x| format!("{}-fixed.gif", x))
+            .unwrap();
+        fs::write(&path, data).await?;
+        tracing::info!("Fixed gif saved as ({:?}).", path);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{codecs::gif::GifEncoder, Frame, RgbaImage};
+
+    use super::Gif;
+
+    #[test]
+    fn test_fix() {
+        let pixels = RgbaImage::from_fn(5, 7, |x, y| {
+            image::Rgba([x as u8 * 40, y as u8 * 30, 0, 255])
+        });
+        let mut data = Vec::new();
+        GifEncoder::new(&mut data)
+            .encode_frame(Frame::new(pixels))
+            .unwrap();
+
+        let gif = Gif::parse(&data).unwrap();
+        assert_eq!((gif.width, gif.height), (5, 7));
+        assert_eq!(gif.images.len(), 1);
+        assert_eq!(gif.images[0].pixels, 35);
+        assert!(!gif.fix(&mut data.clone()));
+
+        let offset = gif.images[0].offset;
+        data[8..10].copy_from_slice(&3_u16.to_le_bytes());
+        data[offset + 7..offset + 9].copy_from_slice(&3_u16.to_le_bytes());
+        let gif = Gif::parse(&data).unwrap();
+        assert_eq!(gif.images[0].height, 3);
+
+        assert!(gif.fix(&mut data));
+        let gif = Gif::parse(&data).unwrap();
+        assert_eq!((gif.width, gif.height), (5, 7));
+        assert_eq!(gif.images[0].height, 7);
+    }
+
+    #[test]
+    fn test_decoded_len() {
+        // clear, 0, 1, 6 (= "0 1"), end; the table fills up before the end code widens to 4 bits
+        let codes = [4_u16, 0, 1, 6, 5];
+        let mut acc = 0_u32;
+        for (i, code) in codes.iter().enumerate() {
+            acc |= (*code as u32) << (i * 3);
+        }
+        assert_eq!(Gif::decoded_len(2, &acc.to_le_bytes()), 4);
+        assert_eq!(Gif::decoded_len(0, &[0xff]), 0);
+    }
+}
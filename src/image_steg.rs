@@ -2,7 +2,7 @@ use std::{fs::File, path::PathBuf};
 
 use anyhow::Result;
 use async_trait::async_trait;
-use image::{io::Reader as ImageReader, Rgba, RgbaImage};
+use image::{ImageReader, Rgba, RgbaImage};
 use png::{BitDepth, ColorType, Encoder as PngEncoder};
 use tokio::fs::{create_dir_all, write};
 use tracing::instrument;
@@ -225,7 +225,7 @@ mod test {
     use std::io::Cursor;
 
     use anyhow::Result;
-    use image::io::Reader as ImageReader;
+    use image::ImageReader;
     use once_cell::sync::Lazy;
 
     use super::ImageSteg;
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use async_trait::async_trait;
-use image::{io::Reader as ImageReader, GenericImageView};
+use image::{GenericImageView, ImageReader};
 
 use crate::Command;
 
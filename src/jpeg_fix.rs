@@ -0,0 +1,446 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::fs;
+
+use crate::{error::Error, Command};
+
+#[derive(Debug)]
+pub struct JpegFix {
+    file: String,
+    width: Option<u16>,
+    height: Option<u16>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Component {
+    id: u8,
+    h: u8,
+    v: u8,
+}
+
+#[derive(Debug)]
+struct Frame {
+    marker: u8,
+    offset: usize,
+    height: u16,
+    width: u16,
+    components: Vec<Component>,
+}
+
+#[derive(Debug)]
+struct Scan {
+    components: Vec<(Component, usize, usize)>,
+    spectral_end: u8,
+    offset: usize,
+}
+
+#[derive(Debug)]
+struct Huffman {
+    mincode: [i32; 16],
+    maxcode: [i32; 16],
+    valptr: [i32; 16],
+    values: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8], values: &[u8]) -> Self {
+        let mut huffman = Self {
+            mincode: [0; 16],
+            maxcode: [-1; 16],
+            valptr: [0; 16],
+            values: values.to_vec(),
+        };
+
+        let (mut code, mut k) = (0, 0);
+        for (l, &count) in counts.iter().enumerate() {
+            huffman.valptr[l] = k;
+            huffman.mincode[l] = code;
+            code += count as i32;
+            k += count as i32;
+            if count != 0 {
+                huffman.maxcode[l] = code - 1;
+            }
+            code <<= 1;
+        }
+
+        huffman
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Option<u8> {
+        let mut code = 0;
+        for l in 0..16 {
+            code = (code << 1) | reader.bit()? as i32;
+            if code <= self.maxcode[l] {
+                let i = self.valptr[l] + code - self.mincode[l];
+                return self.values.get(i as usize).copied();
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Debug)]
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u8,
+    count: u8,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Option<u8> {
+        if self.count == 0 {
+            let byte = *self.data.get(self.pos)?;
+            if byte == 0xff {
+                // Any marker other than the stuffed 0xff00 ends the entropy-coded segment.
+                if *self.data.get(self.pos + 1)? != 0 {
+                    return None;
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            self.bits = byte;
+            self.count = 8;
+        }
+
+        self.count -= 1;
+        Some((self.bits >> self.count) & 1)
+    }
+
+    fn receive(&mut self, n: u8) -> Option<()> {
+        (0..n).try_for_each(|_| self.bit().map(|_| ()))
+    }
+
+    fn restart(&mut self) -> bool {
+        self.count = 0;
+        match self.data.get(self.pos..self.pos + 2) {
+            Some(&[0xff, 0xd0..=0xd7]) => {
+                self.pos += 2;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Jpeg {
+    frame: Option<Frame>,
+    dc: [Option<Huffman>; 4],
+    ac: [Option<Huffman>; 4],
+    restart_interval: u16,
+    scan: Option<Scan>,
+}
+
+impl Jpeg {
+    /// Reads the markers up to the first scan, which is all the MCU count needs.
+    fn parse(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(&[0xff, 0xd8]) {
+            return Err(Error::JpegMarker(0).into());
+        }
+
+        let mut jpeg = Self::default();
+        let mut pos = 2;
+        while jpeg.scan.is_none() {
+            let Some(&[0xff, marker]) = data.get(pos..pos + 2) else {
+                return Err(Error::JpegMarker(pos).into());
+            };
+            match marker {
+                0xff => {
+                    pos += 1;
+                    continue;
+                }
+                0x01 | 0xd0..=0xd7 => {
+                    pos += 2;
+                    continue;
+                }
+                0xd9 => break,
+                _ => (),
+            }
+
+            let length = data
+                .get(pos + 2..pos + 4)
+                .map(|x| u16::from_be_bytes([x[0], x[1]]) as usize)
+                .filter(|&x| x >= 2)
+                .ok_or(Error::JpegMarker(pos))?;
+            let segment = data
+                .get(pos + 4..pos + 2 + length)
+                .ok_or(Error::JpegMarker(pos))?;
+            match marker {
+                0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                    jpeg.frame = Some(Self::parse_frame(marker, pos, segment)?);
+                }
+                0xc4 => jpeg.parse_huffman(segment).ok_or(Error::JpegMarker(pos))?,
+                0xdd => {
+                    let interval = segment.get(..2).ok_or(Error::JpegMarker(pos))?;
+                    jpeg.restart_interval = u16::from_be_bytes([interval[0], interval[1]]);
+                }
+                0xda => {
+                    let scan = jpeg.parse_scan(segment, pos + 2 + length);
+                    jpeg.scan = Some(scan.ok_or(Error::JpegMarker(pos))?);
+                }
+                _ => (),
+            }
+            pos += 2 + length;
+        }
+
+        Ok(jpeg)
+    }
+
+    fn parse_frame(marker: u8, pos: usize, segment: &[u8]) -> Result<Frame, Error> {
+        let count = *segment.get(5).ok_or(Error::JpegMarker(pos))? as usize;
+        let components = segment
+            .get(6..6 + count * 3)
+            .ok_or(Error::JpegMarker(pos))?
+            .chunks_exact(3)
+            .map(|x| Component {
+                id: x[0],
+                h: (x[1] >> 4).max(1),
+                v: (x[1] & 0x0f).max(1),
+            })
+            .collect();
+
+        Ok(Frame {
+            marker,
+            offset: pos + 5,
+            height: u16::from_be_bytes([segment[1], segment[2]]),
+            width: u16::from_be_bytes([segment[3], segment[4]]),
+            components,
+        })
+    }
+
+    fn parse_huffman(&mut self, mut segment: &[u8]) -> Option<()> {
+        while let Some(&class) = segment.first() {
+            let counts = segment.get(1..17)?;
+            let total = counts.iter().map(|&x| x as usize).sum::<usize>();
+            let values = segment.get(17..17 + total)?;
+
+            let huffman = Some(Huffman::new(counts, values));
+            match class >> 4 {
+                0 => *self.dc.get_mut(class as usize & 3)? = huffman,
+                _ => *self.ac.get_mut(class as usize & 3)? = huffman,
+            }
+            segment = &segment[17 + total..];
+        }
+
+        Some(())
+    }
+
+    fn parse_scan(&self, segment: &[u8], offset: usize) -> Option<Scan> {
+        let frame = self.frame.as_ref()?;
+        let count = *segment.first()? as usize;
+        let components = segment
+            .get(1..1 + count * 2)?
+            .chunks_exact(2)
+            .map(|x| {
+                let component = frame.components.iter().find(|c| c.id == x[0])?;
+                Some((*component, (x[1] >> 4) as usize & 3, x[1] as usize & 3))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Scan {
+            components,
+            spectral_end: *segment.get(1 + count * 2 + 1)?,
+            offset,
+        })
+    }
+
+    /// Decodes the first scan and counts the MCUs it holds before the data runs out.
+    fn count_mcus(&self, data: &[u8]) -> Option<u32> {
+        let scan = self.scan.as_ref()?;
+        let tables = scan
+            .components
+            .iter()
+            .map(|&(c, dc, ac)| {
+                let blocks = if scan.components.len() > 1 {
+                    c.h * c.v
+                } else {
+                    1
+                };
+                let ac = self.ac[ac].as_ref().filter(|_| scan.spectral_end > 0);
+                Some((blocks, self.dc[dc].as_ref()?, ac))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut reader = BitReader {
+            data,
+            pos: scan.offset,
+            bits: 0,
+            count: 0,
+        };
+        let interval = self.restart_interval as u32;
+        let mut mcus = 0;
+        loop {
+            if interval != 0 && mcus != 0 && mcus % interval == 0 && !reader.restart() {
+                break;
+            }
+
+            let decoded = tables.iter().try_for_each(|&(blocks, dc, ac)| {
+                (0..blocks).try_for_each(|_| Self::decode_block(&mut reader, dc, ac))
+            });
+            if decoded.is_none() {
+                break;
+            }
+            mcus += 1;
+        }
+
+        Some(mcus)
+    }
+
+    fn decode_block(reader: &mut BitReader, dc: &Huffman, ac: Option<&Huffman>) -> Option<()> {
+        let size = dc.decode(reader)?;
+        reader.receive(size)?;
+
+        let Some(ac) = ac else {
+            return Some(());
+        };
+        let mut k = 1;
+        while k < 64 {
+            let rs = ac.decode(reader)?;
+            let (run, size) = (rs >> 4, rs & 0x0f);
+            if size == 0 {
+                if run != 15 {
+                    break;
+                }
+                k += 16;
+                continue;
+            }
+
+            k += run as u32;
+            reader.receive(size)?;
+            k += 1;
+        }
+
+        Some(())
+    }
+
+    /// Converts an MCU count back to a height, rounded up to whole MCU rows.
+    fn height_from_mcus(&self, width: u16, mcus: u32) -> Option<u16> {
+        let frame = self.frame.as_ref()?;
+        let scan = self.scan.as_ref()?;
+        let h_max = frame.components.iter().map(|x| x.h).max()? as u32;
+        let v_max = frame.components.iter().map(|x| x.v).max()? as u32;
+
+        let (mcu_width, mcu_height) = match scan.components.as_slice() {
+            [(c, _, _)] => (8 * h_max / c.h as u32, 8 * v_max / c.v as u32),
+            _ => (8 * h_max, 8 * v_max),
+        };
+        let per_row = (width as u32).div_ceil(mcu_width);
+        let height = mcus.checked_div(per_row)? * mcu_height;
+
+        u16::try_from(height).ok()
+    }
+}
+
+impl JpegFix {
+    pub fn new(file: String, width: Option<u16>, height: Option<u16>) -> Self {
+        Self {
+            file,
+            width,
+            height,
+        }
+    }
+}
+
+#[async_trait]
+impl Command for JpegFix {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self {
+            file,
+            width,
+            height,
+        } = *self;
+
+        let mut data = fs::read(&file).await?;
+        let jpeg = Jpeg::parse(&data)?;
+        let frame = jpeg.frame.as_ref().ok_or(Error::JpegSof)?;
+        tracing::info!(
+            "Read SOF{} at offset({:#x}) with width({}), height({}).",
+            frame.marker - 0xc0,
+            frame.offset,
+            frame.width,
+            frame.height,
+        );
+
+        let width = width.unwrap_or(frame.width);
+        let estimated = jpeg
+            .count_mcus(&data)
+            .and_then(|mcus| {
+                tracing::info!("Decoded {} MCUs from the first scan.", mcus);
+                jpeg.height_from_mcus(width, mcus)
+            })
+            .filter(|&x| x != 0);
+        if let Some(estimated) = estimated {
+            tracing::info!("Estimated height({}) from the MCU count.", estimated);
+        }
+
+        let Some(height) = height.or(estimated) else {
+            return Err(anyhow::anyhow!(
+                "Failed to decode the MCUs of the first scan, pass --height instead."
+            ));
+        };
+
+        let offset = frame.offset;
+        data[offset..offset + 2].copy_from_slice(&height.to_be_bytes());
+        data[offset + 2..offset + 4].copy_from_slice(&width.to_be_bytes());
+
+        let path = PathBuf::from(&file)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .map(|x| format!("{}-fixed.jpg", x))
+            .unwrap();
+        fs::write(&path, &data).await?;
+        tracing::info!("Fixed jpeg with height({}) saved as ({:?}).", height, path);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{codecs::jpeg::JpegEncoder, ExtendedColorType};
+
+    use super::Jpeg;
+
+    fn encode(width: u32, height: u32, color: ExtendedColorType) -> Vec<u8> {
+        let channels = if color == ExtendedColorType::L8 { 1 } else { 3 };
+        let pixels = (0..width * height * channels)
+            .map(|x| (x * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+
+        let mut data = Vec::new();
+        JpegEncoder::new(&mut data)
+            .encode(&pixels, width, height, color)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_count_mcus_gray() {
+        let mut data = encode(24, 40, ExtendedColorType::L8);
+        let jpeg = Jpeg::parse(&data).unwrap();
+        let offset = jpeg.frame.as_ref().unwrap().offset;
+        data[offset..offset + 2].copy_from_slice(&8_u16.to_be_bytes());
+
+        let jpeg = Jpeg::parse(&data).unwrap();
+        assert_eq!(jpeg.frame.as_ref().unwrap().height, 8);
+        assert_eq!(jpeg.count_mcus(&data), Some(15));
+        assert_eq!(jpeg.height_from_mcus(24, 15), Some(40));
+    }
+
+    #[test]
+    fn test_count_mcus_rgb() {
+        let data = encode(30, 33, ExtendedColorType::Rgb8);
+        let jpeg = Jpeg::parse(&data).unwrap();
+        let frame = jpeg.frame.as_ref().unwrap();
+        let (h, v) = (frame.components[0].h as u32, frame.components[0].v as u32);
+
+        let mcus = jpeg.count_mcus(&data).unwrap();
+        assert_eq!(mcus, 30_u32.div_ceil(8 * h) * 33_u32.div_ceil(8 * v));
+        let height = jpeg.height_from_mcus(30, mcus).unwrap() as u32;
+        assert_eq!(height, 33_u32.div_ceil(8 * v) * 8 * v);
+    }
+}
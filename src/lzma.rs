@@ -0,0 +1,289 @@
+use crate::error::Error;
+
+const NUM_STATES: usize = 12;
+const POS_BITS_MAX: usize = 4;
+const END_POS_MODEL_INDEX: usize = 14;
+const NUM_FULL_DISTANCES: usize = 1 << (END_POS_MODEL_INDEX >> 1);
+const MATCH_MIN_LEN: usize = 2;
+const PROB_INIT: u16 = 1 << 10;
+
+struct RangeDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> RangeDecoder<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, Error> {
+        let init = data.get(..5).ok_or(Error::Lzma)?;
+        if init[0] != 0 {
+            return Err(Error::Lzma);
+        }
+
+        Ok(Self {
+            data,
+            pos: 5,
+            range: u32::MAX,
+            code: u32::from_be_bytes(init[1..5].try_into().unwrap()),
+        })
+    }
+
+    fn normalize(&mut self) -> Result<(), Error> {
+        if self.range < 1 << 24 {
+            let byte = *self.data.get(self.pos).ok_or(Error::Lzma)?;
+            self.range <<= 8;
+            self.code = (self.code << 8) | byte as u32;
+            self.pos += 1;
+        }
+
+        Ok(())
+    }
+
+    fn bit(&mut self, prob: &mut u16) -> Result<u32, Error> {
+        let bound = (self.range >> 11) * *prob as u32;
+        let bit = if self.code < bound {
+            *prob += (2048 - *prob) >> 5;
+            self.range = bound;
+            0
+        } else {
+            *prob -= *prob >> 5;
+            self.code -= bound;
+            self.range -= bound;
+            1
+        };
+        self.normalize()?;
+
+        Ok(bit)
+    }
+
+    fn direct_bits(&mut self, count: usize) -> Result<u32, Error> {
+        let mut res = 0;
+        for _ in 0..count {
+            self.range >>= 1;
+            let bit = (self.code >= self.range) as u32;
+            if bit == 1 {
+                self.code -= self.range;
+            }
+            self.normalize()?;
+            res = (res << 1) | bit;
+        }
+
+        Ok(res)
+    }
+
+    fn tree(&mut self, probs: &mut [u16], bits: usize) -> Result<usize, Error> {
+        let mut m = 1;
+        for _ in 0..bits {
+            m = (m << 1) | self.bit(&mut probs[m])? as usize;
+        }
+
+        Ok(m - (1 << bits))
+    }
+
+    fn reverse_tree(&mut self, probs: &mut [u16], bits: usize) -> Result<usize, Error> {
+        let (mut m, mut symbol) = (1, 0);
+        for i in 0..bits {
+            let bit = self.bit(&mut probs[m])? as usize;
+            m = (m << 1) | bit;
+            symbol |= bit << i;
+        }
+
+        Ok(symbol)
+    }
+}
+
+struct LenDecoder {
+    choice: u16,
+    choice2: u16,
+    low: Vec<[u16; 1 << 3]>,
+    mid: Vec<[u16; 1 << 3]>,
+    high: [u16; 1 << 8],
+}
+
+impl LenDecoder {
+    fn new() -> Self {
+        Self {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: vec![[PROB_INIT; 1 << 3]; 1 << POS_BITS_MAX],
+            mid: vec![[PROB_INIT; 1 << 3]; 1 << POS_BITS_MAX],
+            high: [PROB_INIT; 1 << 8],
+        }
+    }
+
+    fn decode(&mut self, rc: &mut RangeDecoder, pos_state: usize) -> Result<usize, Error> {
+        if rc.bit(&mut self.choice)? == 0 {
+            return rc.tree(&mut self.low[pos_state], 3);
+        }
+        if rc.bit(&mut self.choice2)? == 0 {
+            return Ok(8 + rc.tree(&mut self.mid[pos_state], 3)?);
+        }
+
+        Ok(16 + rc.tree(&mut self.high, 8)?)
+    }
+}
+
+/// Decodes a raw LZMA stream with 5 bytes of `props` until `size` bytes are out or the end
+/// marker is reached.
+pub fn decompress(props: &[u8], data: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+    let props = props.get(..5).ok_or(Error::Lzma)?;
+    if props[0] >= 9 * 5 * 5 {
+        return Err(Error::Lzma);
+    }
+    let (lc, lp, pb) = (
+        props[0] as usize % 9,
+        props[0] as usize / 9 % 5,
+        props[0] as usize / 45,
+    );
+
+    let mut rc = RangeDecoder::new(data)?;
+    let mut literals = vec![PROB_INIT; 0x300 << (lc + lp)];
+    let mut is_match = [PROB_INIT; NUM_STATES << POS_BITS_MAX];
+    let mut is_rep = [PROB_INIT; NUM_STATES];
+    let mut is_rep_g0 = [PROB_INIT; NUM_STATES];
+    let mut is_rep_g1 = [PROB_INIT; NUM_STATES];
+    let mut is_rep_g2 = [PROB_INIT; NUM_STATES];
+    let mut is_rep0_long = [PROB_INIT; NUM_STATES << POS_BITS_MAX];
+    let mut pos_slot = [[PROB_INIT; 1 << 6]; 4];
+    let mut pos_special = [PROB_INIT; 1 + NUM_FULL_DISTANCES - END_POS_MODEL_INDEX];
+    let mut align = [PROB_INIT; 1 << 4];
+    let mut len_decoder = LenDecoder::new();
+    let mut rep_len_decoder = LenDecoder::new();
+
+    // `size` is untrusted, so only reserve what a generous ratio allows.
+    let mut out = Vec::with_capacity(size.min(data.len().saturating_mul(64)));
+    let (mut state, mut reps) = (0, [0_usize; 4]);
+    while out.len() < size {
+        let pos_state = out.len() & ((1 << pb) - 1);
+        if rc.bit(&mut is_match[(state << POS_BITS_MAX) + pos_state])? == 0 {
+            let prev = out.last().copied().unwrap_or_default() as usize;
+            let lit_state = ((out.len() & ((1 << lp) - 1)) << lc) + (prev >> (8 - lc));
+            let probs = &mut literals[0x300 * lit_state..0x300 * (lit_state + 1)];
+            let mut symbol = 1;
+            if state >= 7 {
+                let mut match_byte = out[out.len() - reps[0] - 1] as usize;
+                while symbol < 0x100 {
+                    let match_bit = (match_byte >> 7) & 1;
+                    match_byte <<= 1;
+                    let bit = rc.bit(&mut probs[((1 + match_bit) << 8) + symbol])? as usize;
+                    symbol = (symbol << 1) | bit;
+                    if match_bit != bit {
+                        break;
+                    }
+                }
+            }
+            while symbol < 0x100 {
+                symbol = (symbol << 1) | rc.bit(&mut probs[symbol])? as usize;
+            }
+            out.push((symbol - 0x100) as u8);
+            state = match state {
+                0..4 => 0,
+                4..10 => state - 3,
+                _ => state - 6,
+            };
+            continue;
+        }
+
+        let len = if rc.bit(&mut is_rep[state])? == 1 {
+            if rc.bit(&mut is_rep_g0[state])? == 0 {
+                if rc.bit(&mut is_rep0_long[(state << POS_BITS_MAX) + pos_state])? == 0 {
+                    if reps[0] >= out.len() {
+                        return Err(Error::Lzma);
+                    }
+                    state = if state < 7 { 9 } else { 11 };
+                    out.push(out[out.len() - reps[0] - 1]);
+                    continue;
+                }
+            } else {
+                let dist = if rc.bit(&mut is_rep_g1[state])? == 0 {
+                    reps[1]
+                } else if rc.bit(&mut is_rep_g2[state])? == 0 {
+                    let dist = reps[2];
+                    reps[2] = reps[1];
+                    dist
+                } else {
+                    let dist = reps[3];
+                    reps[3] = reps[2];
+                    reps[2] = reps[1];
+                    dist
+                };
+                reps[1] = reps[0];
+                reps[0] = dist;
+            }
+            state = if state < 7 { 8 } else { 11 };
+            rep_len_decoder.decode(&mut rc, pos_state)?
+        } else {
+            reps.copy_within(0..3, 1);
+            let len = len_decoder.decode(&mut rc, pos_state)?;
+            state = if state < 7 { 7 } else { 10 };
+
+            let slot = rc.tree(&mut pos_slot[len.min(3)], 6)?;
+            reps[0] = match slot {
+                0..4 => slot,
+                _ => {
+                    let bits = (slot >> 1) - 1;
+                    let base = (2 | (slot & 1)) << bits;
+                    if slot < END_POS_MODEL_INDEX {
+                        base + rc.reverse_tree(&mut pos_special[base - slot..], bits)?
+                    } else {
+                        let high = (rc.direct_bits(bits - 4)? as usize) << 4;
+                        base + high + rc.reverse_tree(&mut align, 4)?
+                    }
+                }
+            };
+            if reps[0] == u32::MAX as usize {
+                break;
+            }
+            len
+        };
+
+        let len = (len + MATCH_MIN_LEN).min(size - out.len());
+        if reps[0] >= out.len() {
+            return Err(Error::Lzma);
+        }
+        for _ in 0..len {
+            out.push(out[out.len() - reps[0] - 1]);
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompress;
+
+    #[test]
+    fn test_decompress() {
+        // `lzma.compress(pt, lzma.FORMAT_ALONE)`, the second copy is one long distant match.
+        let alone = hex::decode(concat!(
+            "5d00008000ffffffffffffffff0018080286492e680e26ce2bf19262f910ba62",
+            "30b6584dea5f9727411f001d429abc6ed412ca9fa91ac9c09931458b5215bf6c",
+            "77e300d11e6f25775aa12e04e3e83b806ab49142cd15ffedaa3a038fac08d82b",
+            "3522a1d97f353c3ef61a149d1926fcef535e86e1ce02528faf00955fbea10c7f",
+            "8a8ccf722c78841d9b74acb9f256057a16ed62ad4ad3a4a31da595944a0c83e7",
+            "76cfe5cd4860d51dd5251ff9647973588322fe05f384723d7695c68b0aca4b35",
+            "aafe44c4464250a720cac91de2112216ab6028db23c10a2ba5d8660cc113558f",
+            "33ac9052918aa1a7248eada1e78337c9119e3fe8ee8ae66ad43f211460b6b338",
+            "739386e24dfdf6686395f1cec63c0e98d5fd612114229148f2074498fa167878",
+            "2d31f8384cd7343e249926ba2cf36b199bd0eba0f3e0322decf6bfed9f253376",
+            "8e08258ad8e382fc0fad7067c5303bb42e1bca1ffad87ba7f5687f7d62c4ec11",
+            "ca6c3b8348fdb1172451f3fd934d12fb55c73109513e031387f18f4a1b1e29cc",
+            "b30653bb1190a02167a38ed23413949128d7469a39e70ae42925efd45cd29aa9",
+            "bf2e1d65af8e02f34898041e1656009408915f0ef0d7932b50dec65a9b8f6de6",
+            "9598fff71bece4",
+        ))
+        .unwrap();
+        let numbers = (0..200)
+            .map(|i| (i * i % 997).to_string())
+            .collect::<Vec<_>>();
+        let pt = format!("{} flag{{lzma}} ", numbers.join(" ")).repeat(2);
+        let out = decompress(&alone[..5], &alone[13..], pt.len()).unwrap();
+        assert_eq!(out, pt.as_bytes());
+
+        assert!(decompress(&alone[..5], &alone[13..40], pt.len()).is_err());
+        assert!(decompress(&[0xff; 5], &alone[13..], pt.len()).is_err());
+        assert!(decompress(&alone[..5], &alone[13..40], usize::MAX).is_err());
+    }
+}
@@ -8,25 +8,54 @@ use tracing::level_filters::LevelFilter;
 use crate::{
     base64_steg::Base64Steg,
     behinder::BehinderTrafficAnalyse,
+    bmp_fix::BmpFix,
     cli::{Cli, CliCommand},
+    crc_util::CrcUtil,
+    gif_fix::GifFix,
     image_steg::ImageSteg,
     image_util::ImageUtil,
+    jpeg_fix::JpegFix,
     key_traffic::KeyTraffic,
     mouse_traffic::MouseTraffic,
-    png_crc::PngCrc,
+    png_apng::PngApng,
+    png_cgbi::PngCgbi,
+    png_crc::{PngCrc, PngCrcMode},
+    png_idat::PngIdat,
     zip_crc::ZipCrc,
+    zip_kpa::ZipKpa,
+    zip_pass::ZipPass,
+    zip_pseudo::ZipPseudo,
+    zip_repair::ZipRepair,
 };
 
+mod archive;
 mod base64_steg;
 mod behinder;
+mod bmp_fix;
 mod cli;
+mod crc;
+mod crc_util;
 mod error;
+mod gif_fix;
 mod image_steg;
 mod image_util;
+mod jpeg_fix;
 mod key_traffic;
+mod lzma;
+mod mask;
 mod mouse_traffic;
+mod png_apng;
+mod png_cgbi;
+mod png_chunk;
 mod png_crc;
+mod png_idat;
+mod rank;
 mod zip_crc;
+mod zip_crypto;
+mod zip_kpa;
+mod zip_pass;
+mod zip_pseudo;
+mod zip_repair;
 
 #[async_trait]
 pub trait Command: std::fmt::Debug {
@@ -36,12 +65,75 @@ pub trait Command: std::fmt::Debug {
 impl From<CliCommand> for Box<dyn Command> {
     fn from(cli_command: CliCommand) -> Self {
         match cli_command {
-            CliCommand::PngCrc { file } => Box::new(PngCrc::new(file)),
-            CliCommand::ZipCrc {
+            CliCommand::PngCrc {
+                file,
+                fix_crc,
+                from_idat,
+                others,
+                carve,
+                repair,
+                max,
+            } => {
+                let mode = [
+                    (fix_crc, PngCrcMode::FixCrc),
+                    (from_idat, PngCrcMode::FromIdat),
+                    (others, PngCrcMode::Others),
+                    (carve, PngCrcMode::Carve),
+                    (repair, PngCrcMode::Repair),
+                ]
+                .into_iter()
+                .find_map(|(set, mode)| set.then_some(mode))
+                .unwrap_or(PngCrcMode::Dimensions);
+                Box::new(PngCrc::new(file, mode, max))
+            }
+            CliCommand::PngCgbi { file } => Box::new(PngCgbi::new(file)),
+            CliCommand::PngApng { file } => Box::new(PngApng::new(file)),
+            CliCommand::PngIdat { file } => Box::new(PngIdat::new(file)),
+            CliCommand::JpegFix {
+                file,
+                width,
+                height,
+            } => Box::new(JpegFix::new(file, width, height)),
+            CliCommand::BmpFix { file } => Box::new(BmpFix::new(file)),
+            CliCommand::GifFix { file } => Box::new(GifFix::new(file)),
+            CliCommand::Crc {
                 file,
+                algorithm,
+                target,
                 size,
+                offset,
+                charset,
+                mask,
+            } => Box::new(CrcUtil::new(
+                file, algorithm, target, size, offset, charset, mask,
+            )),
+            CliCommand::ZipCrc {
+                file,
+                max_size,
                 alphabet,
-            } => Box::new(ZipCrc::new(file, size, alphabet)),
+                charset,
+                masks,
+                templates,
+                pattern,
+            } => Box::new(ZipCrc::new(
+                file, max_size, alphabet, charset, masks, templates, pattern,
+            )),
+            CliCommand::ZipKpa {
+                file,
+                entry,
+                plain,
+                plain_zip,
+                offset,
+                password,
+            } => Box::new(ZipKpa::new(file, entry, plain, plain_zip, offset, password)),
+            CliCommand::ZipPass {
+                file,
+                entry,
+                wordlist,
+                mask,
+            } => Box::new(ZipPass::new(file, entry, wordlist, mask)),
+            CliCommand::ZipRepair { file } => Box::new(ZipRepair::new(file)),
+            CliCommand::ZipPseudo { file } => Box::new(ZipPseudo::new(file)),
             CliCommand::Base64Steg { file } => Box::new(Base64Steg::new(file)),
             CliCommand::Behinder { file, outdir, key } => {
                 Box::new(BehinderTrafficAnalyse::new(file, outdir, key))
@@ -0,0 +1,252 @@
+use crate::error::Error;
+
+const SPECIAL: &[u8] = b" !\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+/// Leading mask positions are split off until there are this many work items.
+const SPLIT: usize = 4096;
+
+/// Bytes of the hashcat-style placeholder `?c`.
+pub fn class(c: u8) -> Option<Vec<u8>> {
+    let set = match c {
+        b'l' => (b'a'..=b'z').collect(),
+        b'u' => (b'A'..=b'Z').collect(),
+        b'd' => (b'0'..=b'9').collect(),
+        b'h' => (b'0'..=b'9').chain(b'a'..=b'f').collect(),
+        b'H' => (b'0'..=b'9').chain(b'A'..=b'F').collect(),
+        b's' => SPECIAL.to_vec(),
+        b'a' => b"luds".iter().flat_map(|&x| class(x).unwrap()).collect(),
+        b'b' => (0..=u8::MAX).collect(),
+        b'?' => vec![b'?'],
+        _ => return None,
+    };
+
+    Some(set)
+}
+
+/// Parses a hashcat-style mask into the bytes allowed at each position. Given `any`, a `?`
+/// that does not start a placeholder, `??` included, stands for one byte of `any`.
+pub fn parse(mask: &str, any: Option<&[u8]>) -> Result<Vec<Vec<u8>>, Error> {
+    let bytes = mask.as_bytes();
+    let mut positions = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'?' => {
+                let set = bytes
+                    .get(i + 1)
+                    .filter(|&&x| any.is_none() || x != b'?')
+                    .and_then(|&x| class(x));
+                match (set, any) {
+                    (Some(set), _) => {
+                        positions.push(set);
+                        i += 2;
+                    }
+                    (None, Some(any)) => {
+                        positions.push(any.to_vec());
+                        i += 1;
+                    }
+                    (None, None) => return Err(Error::Mask(i)),
+                }
+            }
+            c => {
+                positions.push(vec![c]);
+                i += 1;
+            }
+        }
+    }
+
+    Ok(positions)
+}
+
+pub fn preset(name: &str) -> Option<Vec<u8>> {
+    let set = match name {
+        "digits" => class(b'd')?,
+        "lower" => class(b'l')?,
+        "upper" => class(b'u')?,
+        "hex" => class(b'h')?,
+        "base64" => (b'A'..=b'Z')
+            .chain(b'a'..=b'z')
+            .chain(b'0'..=b'9')
+            .chain(*b"+/=")
+            .collect(),
+        "printable" => (b' '..=b'~').chain(*b"\t\n\r\x0b\x0c").collect(),
+        _ => return None,
+    };
+
+    Some(set)
+}
+
+/// Parses a comma separated list of presets, hex bytes and hex ranges such as `00-ff` into
+/// the alphabet bytes, without duplicates.
+pub fn parse_charset(charset: &str) -> Result<Vec<u8>, Error> {
+    let hex = |x: &str| match x.len() {
+        2 => u8::from_str_radix(x, 16).ok(),
+        _ => None,
+    };
+
+    let mut alphabet = Vec::new();
+    for item in charset.split(',').map(str::trim) {
+        let bytes = match (preset(item), item.split_once('-')) {
+            (Some(set), _) => set,
+            (None, Some((lo, hi))) => match (hex(lo), hex(hi)) {
+                (Some(lo), Some(hi)) if lo <= hi => (lo..=hi).collect(),
+                _ => return Err(Error::Charset(item.to_owned())),
+            },
+            (None, None) => vec![hex(item).ok_or(Error::Charset(item.to_owned()))?],
+        };
+        alphabet.extend(bytes);
+    }
+
+    Ok(dedup(alphabet))
+}
+
+pub fn dedup(bytes: Vec<u8>) -> Vec<u8> {
+    let mut seen = [false; 256];
+    bytes
+        .into_iter()
+        .filter(|&x| !std::mem::replace(&mut seen[x as usize], true))
+        .collect()
+}
+
+/// Counts the leading positions of `mask` to split off so their product gives at least
+/// `SPLIT` work items, returning the count and the number of items. Known bytes multiply
+/// by one, so a known prefix is split past.
+pub fn split(mask: &[Vec<u8>]) -> (usize, usize) {
+    let (mut split, mut items) = (0, 1_usize);
+    while split < mask.len() && items < SPLIT {
+        items = items.saturating_mul(mask[split].len());
+        split += 1;
+    }
+
+    (split, items)
+}
+
+/// The `i`th combination of bytes from `sets`, the last position varying fastest.
+pub fn nth(sets: &[Vec<u8>], i: usize) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(sets.len());
+    let mut rest = i;
+    for set in sets.iter().rev() {
+        prefix.push(set[rest % set.len()]);
+        rest /= set.len();
+    }
+    prefix.reverse();
+    prefix
+}
+
+/// Enumerates every completion of `curr` with bytes from `rest` depth first until `visit`
+/// accepts one. The `step`ped state of each prefix is kept, so a completion costs one step
+/// instead of one per byte.
+pub fn walk<S: Copy>(
+    mut curr: Vec<u8>,
+    state: S,
+    rest: &[Vec<u8>],
+    step: impl Fn(S, u8) -> S,
+    mut visit: impl FnMut(&[u8], S) -> bool,
+) -> Option<Vec<u8>> {
+    let Some(first) = rest.first() else {
+        return visit(&curr, state).then_some(curr);
+    };
+
+    let mut states = vec![state];
+    let mut stack = vec![first.iter()];
+    while let Some(bytes) = stack.last_mut() {
+        if let Some(&b) = bytes.next() {
+            let next = step(*states.last().unwrap(), b);
+            curr.push(b);
+            if stack.len() == rest.len() {
+                if visit(&curr, next) {
+                    return Some(curr);
+                }
+                curr.pop();
+            } else {
+                states.push(next);
+                stack.push(rest[stack.len()].iter());
+            }
+            continue;
+        }
+
+        stack.pop();
+        states.pop();
+        curr.pop();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dedup, nth, parse, parse_charset, split, walk};
+
+    #[test]
+    fn test_parse() {
+        let mask = parse("f?d??", None).unwrap();
+        assert_eq!(mask.len(), 3);
+        assert_eq!(mask[0], b"f");
+        assert_eq!(mask[1], b"0123456789");
+        assert_eq!(mask[2], b"?");
+        assert_eq!(parse("?a", None).unwrap()[0].len(), 95);
+        assert_eq!(parse("?b", None).unwrap()[0].len(), 256);
+        assert!(parse("ab?", None).is_err());
+        assert!(parse("?x", None).is_err());
+
+        let mask = parse("fla??d?", Some(b"xy")).unwrap();
+        assert_eq!(mask.len(), 6);
+        assert_eq!(mask[3], b"xy");
+        assert_eq!(mask[4], b"0123456789");
+        assert_eq!(mask[5], b"xy");
+    }
+
+    #[test]
+    fn test_parse_charset() {
+        assert_eq!(parse_charset("digits").unwrap(), b"0123456789");
+        assert_eq!(parse_charset("hex, 30-39, 7b,7d").unwrap().len(), 18);
+        assert_eq!(parse_charset("00-ff").unwrap().len(), 256);
+        assert_eq!(parse_charset("base64").unwrap().len(), 65);
+        assert_eq!(parse_charset("printable").unwrap().len(), 100);
+        for charset in ["ff-00", "0-9", "g0", "digit"] {
+            assert!(parse_charset(charset).is_err());
+        }
+
+        // The default alphabet counts bytes, so a non-ASCII character adds each of its bytes.
+        assert_eq!(dedup("aé".to_owned().into_bytes()), b"a\xc3\xa9");
+    }
+
+    #[test]
+    fn test_split() {
+        let mask = parse("flag{?l?l?d}", None).unwrap();
+        let (at, items) = split(&mask);
+        assert_eq!((at, items), (8, 6760));
+        assert_eq!(nth(&mask[..at], 0), b"flag{aa0");
+        assert_eq!(nth(&mask[..at], 11), b"flag{ab1");
+        assert_eq!(nth(&mask[..at], items - 1), b"flag{zz9");
+        assert_eq!(split(&mask[..3]), (3, 1));
+    }
+
+    #[test]
+    fn test_walk() {
+        let rest = [b"ab".to_vec(), b"xyz".to_vec()];
+        let mut seen = Vec::new();
+        let found = walk(
+            b"f".to_vec(),
+            0,
+            &rest,
+            |n, _| n + 1,
+            |pt, n| {
+                assert_eq!(n, 2);
+                seen.push(pt.to_vec());
+                false
+            },
+        );
+        assert!(found.is_none());
+        assert_eq!(seen.len(), 6);
+        assert_eq!(seen[0], b"fax");
+        assert_eq!(seen[5], b"fbz");
+
+        let found = walk(b"f".to_vec(), 0, &rest, |n, _| n + 1, |pt, _| pt == b"fay");
+        assert_eq!(found.unwrap(), b"fay");
+        assert_eq!(
+            walk(b"f".to_vec(), 0, &[], |n, _| n, |_, _| true).unwrap(),
+            b"f"
+        );
+    }
+}
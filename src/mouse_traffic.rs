@@ -168,7 +168,7 @@ mod tests {
     #[test]
     fn test_traffic_from_packets() {
         let packets = "683a3135370d0a\n4f4b41598a0b00004a0700000000000000000000b0b4bea6\n0100000000000000\n00ff0000\n0000ff00\n0100060000000000";
-        let traffic = MouseTraffic::traffic_from_packets(&packets);
+        let traffic = MouseTraffic::traffic_from_packets(packets);
         assert_eq!(traffic, vec![(0, -1, 0), (0, 0, -1)]);
     }
 
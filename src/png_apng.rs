@@ -0,0 +1,243 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::fs;
+
+use crate::{
+    error::Error,
+    png_chunk::{self, Chunk, Header, Png, SIGNATURE},
+    Command,
+};
+
+#[derive(Debug)]
+pub struct PngApng {
+    file: String,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct FrameControl {
+    sequence: u32,
+    width: u32,
+    height: u32,
+    x_offset: u32,
+    y_offset: u32,
+    delay_num: u16,
+    delay_den: u16,
+    dispose: u8,
+    blend: u8,
+}
+
+impl FrameControl {
+    fn from(chunk: &Chunk) -> Option<Self> {
+        let data = chunk.data;
+        if data.len() != 26 {
+            return None;
+        }
+
+        let u32_at = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_be_bytes(data[i..i + 2].try_into().unwrap());
+        Some(Self {
+            sequence: u32_at(0),
+            width: u32_at(4),
+            height: u32_at(8),
+            x_offset: u32_at(12),
+            y_offset: u32_at(16),
+            delay_num: u16_at(20),
+            delay_den: u16_at(22),
+            dispose: data[24],
+            blend: data[25],
+        })
+    }
+
+    fn delay(&self) -> f64 {
+        // A zero denominator means the delay is given in 1/100 seconds.
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        self.delay_num as f64 / den as f64
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    control: FrameControl,
+    data: Vec<u8>,
+}
+
+impl PngApng {
+    pub fn new(file: String) -> Self {
+        Self { file }
+    }
+
+    fn frames(png: &Png) -> Vec<Frame> {
+        let mut frames = Vec::<Frame>::new();
+        for chunk in &png.chunks {
+            match &chunk.kind {
+                b"fcTL" => match FrameControl::from(chunk) {
+                    Some(control) => frames.push(Frame {
+                        control,
+                        data: Vec::new(),
+                    }),
+                    None => tracing::warn!("Invalid fcTL at offset({:#x}).", chunk.offset),
+                },
+                // The default image is only a frame when an fcTL precedes it.
+                b"IDAT" => {
+                    if let Some(frame) = frames.last_mut() {
+                        frame.data.extend_from_slice(chunk.data);
+                    }
+                }
+                b"fdAT" => match (frames.last_mut(), chunk.data.get(4..)) {
+                    (Some(frame), Some(data)) => frame.data.extend_from_slice(data),
+                    _ => tracing::warn!("Orphan fdAT at offset({:#x}).", chunk.offset),
+                },
+                _ => (),
+            }
+        }
+
+        frames
+    }
+
+    /// Builds a standalone png from one frame, keeping the chunks shared by every frame.
+    fn encode(png: &Png, ihdr: &Chunk, frame: &Frame) -> Result<Vec<u8>, Error> {
+        // The frame size is written over the IHDR fields, so they have to be there.
+        Header::from(ihdr)?;
+        let mut header = ihdr.data.to_vec();
+        header[0..4].copy_from_slice(&frame.control.width.to_be_bytes());
+        header[4..8].copy_from_slice(&frame.control.height.to_be_bytes());
+
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend(png_chunk::encode_chunk(b"IHDR", &header));
+        png.chunks
+            .iter()
+            .take_while(|x| !matches!(&x.kind, b"IDAT" | b"fdAT"))
+            .filter(|x| !matches!(&x.kind, b"IHDR" | b"acTL" | b"fcTL"))
+            .for_each(|x| bytes.extend(png_chunk::encode_chunk(&x.kind, x.data)));
+        bytes.extend(png_chunk::encode_chunk(b"IDAT", &frame.data));
+        bytes.extend(png_chunk::encode_chunk(b"IEND", &[]));
+
+        Ok(bytes)
+    }
+}
+
+#[async_trait]
+impl Command for PngApng {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self { file } = *self;
+
+        let data = fs::read(&file).await?;
+        let png = Png::parse(&data)?;
+        let Some(actl) = png.chunk(b"acTL").filter(|x| x.data.len() == 8) else {
+            return Err(anyhow::anyhow!("No acTL chunk found."));
+        };
+        tracing::info!(
+            "Read apng with {} frames and {} plays.",
+            u32::from_be_bytes(actl.data[0..4].try_into().unwrap()),
+            u32::from_be_bytes(actl.data[4..8].try_into().unwrap()),
+        );
+
+        let ihdr = png.chunk(b"IHDR").ok_or(Error::PngIhdr)?;
+        let stem = PathBuf::from(&file)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap()
+            .to_owned();
+        for (i, frame) in Self::frames(&png).iter().enumerate() {
+            let path = format!("{}-frame-{}.png", stem, i);
+            fs::write(&path, Self::encode(&png, ihdr, frame)?).await?;
+
+            let control = &frame.control;
+            tracing::info!(
+                "frame={}, seq={}, size={}x{}, offset=({}, {}), delay={}s, dispose={}, blend={}, path={:?}",
+                i,
+                control.sequence,
+                control.width,
+                control.height,
+                control.x_offset,
+                control.y_offset,
+                control.delay(),
+                control.dispose,
+                control.blend,
+                path,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PngApng;
+    use crate::{
+        error::Error,
+        png_chunk::{encode_chunk, Png, SIGNATURE},
+    };
+
+    fn fctl(sequence: u32, width: u32, height: u32, delay: u16) -> Vec<u8> {
+        let mut data = Vec::new();
+        for x in [sequence, width, height, 1, 2] {
+            data.extend_from_slice(&x.to_be_bytes());
+        }
+        data.extend_from_slice(&delay.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        encode_chunk(b"fcTL", &data)
+    }
+
+    #[test]
+    fn test_frames() {
+        let data = [
+            SIGNATURE.to_vec(),
+            encode_chunk(b"IHDR", &[0, 0, 0, 4, 0, 0, 0, 4, 8, 0, 0, 0, 0]),
+            encode_chunk(b"acTL", &[0, 0, 0, 2, 0, 0, 0, 0]),
+            encode_chunk(b"gAMA", &[0, 0, 0xb1, 0x8f]),
+            fctl(0, 4, 4, 10),
+            encode_chunk(b"IDAT", b"first"),
+            fctl(1, 2, 3, 50),
+            encode_chunk(b"fdAT", b"\0\0\0\x02sec"),
+            encode_chunk(b"fdAT", b"\0\0\0\x03ond"),
+            encode_chunk(b"IEND", &[]),
+        ]
+        .concat();
+        let png = Png::parse(&data).unwrap();
+
+        let frames = PngApng::frames(&png);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data, b"first");
+        assert_eq!(frames[1].data, b"second");
+        assert_eq!(frames[1].control.x_offset, 1);
+        assert_eq!(frames[1].control.y_offset, 2);
+        assert_eq!(frames[1].control.delay(), 0.5);
+
+        let ihdr = png.chunk(b"IHDR").unwrap();
+        let frame = PngApng::encode(&png, ihdr, &frames[1]).unwrap();
+        let frame = Png::parse(&frame).unwrap();
+        let kinds = frame.chunks.iter().map(|x| x.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![*b"IHDR", *b"gAMA", *b"IDAT", *b"IEND"]);
+        assert_eq!(&frame.chunks[0].data[..8], &[0, 0, 0, 2, 0, 0, 0, 3]);
+        assert_eq!(frame.chunks[2].data, b"second");
+    }
+
+    #[test]
+    fn test_encode_short_ihdr() {
+        let data = [
+            SIGNATURE.to_vec(),
+            encode_chunk(b"IHDR", &[0, 0, 0, 4]),
+            encode_chunk(b"acTL", &[0, 0, 0, 1, 0, 0, 0, 0]),
+            fctl(0, 4, 4, 10),
+            encode_chunk(b"IDAT", b"first"),
+            encode_chunk(b"IEND", &[]),
+        ]
+        .concat();
+        let png = Png::parse(&data).unwrap();
+
+        let frames = PngApng::frames(&png);
+        let ihdr = png.chunk(b"IHDR").unwrap();
+        assert!(matches!(
+            PngApng::encode(&png, ihdr, &frames[0]),
+            Err(Error::PngIhdr)
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use png::{BitDepth, ColorType, Encoder as PngEncoder};
+use tokio::fs;
+
+use crate::{
+    png_chunk::{self, Png},
+    Command,
+};
+
+#[derive(Debug)]
+pub struct PngCgbi {
+    file: String,
+}
+
+impl PngCgbi {
+    pub fn new(file: String) -> Self {
+        Self { file }
+    }
+
+    /// Swaps BGR(A) back to RGB(A) and undoes the premultiplied alpha.
+    fn normalize(pixels: &mut [u8], channels: usize) {
+        for pixel in pixels.chunks_exact_mut(channels) {
+            pixel.swap(0, 2);
+
+            if channels == 4 {
+                let alpha = pixel[3] as u32;
+                for c in &mut pixel[..3] {
+                    if let Some(x) = (*c as u32 * 255 + alpha / 2).checked_div(alpha) {
+                        *c = x.min(255) as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    fn encode(pixels: &[u8], width: u32, height: u32, color: ColorType) -> Result<Vec<u8>> {
+        let mut png = Vec::new();
+        let mut encoder = PngEncoder::new(&mut png, width, height);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.set_color(color);
+        encoder
+            .write_header()
+            .and_then(|mut x| x.write_image_data(pixels))?;
+
+        Ok(png)
+    }
+
+    /// Drops the CgBI chunk, inflates and unfilters the headerless IDAT stream and encodes
+    /// the normalized pixels as a standard png.
+    fn convert(data: &[u8]) -> Result<Vec<u8>> {
+        let png = Png::parse(data)?;
+        if png.chunk(b"CgBI").is_none() {
+            return Err(anyhow::anyhow!("No CgBI chunk found."));
+        }
+
+        let header = png.header()?;
+        tracing::info!(?header);
+        let (channels, color) = match (header.bit_depth, header.color_type, header.interlace) {
+            (8, 2, 0) => (3, ColorType::Rgb),
+            (8, 6, 0) => (4, ColorType::Rgba),
+            _ => return Err(anyhow::anyhow!("Unsupported CgBI format {:?}.", header)),
+        };
+
+        // CgBI strips the zlib header and checksum from the IDAT stream.
+        let inflated = png_chunk::inflate(&png.idat(), false, header.raw_size()?);
+        tracing::debug!(inflated.consumed, inflated.complete, inflated.truncated);
+        let bits = header.bits_per_pixel()?;
+        let (mut pixels, _) =
+            png_chunk::unfilter(&inflated.data, header.width, header.height, bits)?;
+
+        Self::normalize(&mut pixels, channels);
+        Self::encode(&pixels, header.width, header.height, color)
+    }
+}
+
+#[async_trait]
+impl Command for PngCgbi {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self { file } = *self;
+
+        let data = fs::read(&file).await?;
+        let normalized = Self::convert(&data)?;
+
+        let path = PathBuf::from(file)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .map(|x| format!("{}-normalized.png", x))
+            .unwrap();
+        fs::write(&path, normalized).await?;
+        tracing::info!("Normalized png saved as ({:?}).", path);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use flate2::{write::DeflateEncoder, Compression};
+    use png::Decoder;
+
+    use super::PngCgbi;
+    use crate::png_chunk::{encode_chunk, Png, SIGNATURE};
+
+    /// A 2x2 CgBI image with premultiplied BGRA rows behind a sub and an up filter.
+    fn cgbi() -> Vec<u8> {
+        let raw = [
+            1, 0x30, 0x20, 0x10, 0xff, 0xd0, 0x20, 0x10, 0x81, //
+            2, 0xd1, 0xe2, 0xf3, 0x01, 0x00, 0xc0, 0xe0, 0x80,
+        ];
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&raw).unwrap();
+
+        [
+            SIGNATURE.to_vec(),
+            encode_chunk(b"CgBI", &[0x50, 0x00, 0x20, 0x06]),
+            encode_chunk(b"IHDR", &[0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]),
+            encode_chunk(b"IDAT", &encoder.finish().unwrap()),
+            encode_chunk(b"IEND", &[]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_convert() {
+        let png = PngCgbi::convert(&cgbi()).unwrap();
+        assert!(Png::parse(&png).unwrap().chunk(b"CgBI").is_none());
+
+        let mut reader = Decoder::new(Cursor::new(png)).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (2, 2));
+        assert_eq!(
+            pixels,
+            vec![
+                0x10, 0x20, 0x30, 0xff, 0x40, 0x80, 0x00, 0x80, //
+                0x03, 0x02, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ]
+        );
+
+        let mut plain = cgbi();
+        plain.drain(8..24);
+        assert!(PngCgbi::convert(&plain).is_err());
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut pixels = vec![
+            0x10, 0x20, 0x30, 0xff, //
+            0x20, 0x40, 0x00, 0x80, //
+            0x01, 0x02, 0x03, 0x00,
+        ];
+        PngCgbi::normalize(&mut pixels, 4);
+        assert_eq!(
+            pixels,
+            vec![
+                0x30, 0x20, 0x10, 0xff, //
+                0x00, 0x80, 0x40, 0x80, //
+                0x03, 0x02, 0x01, 0x00,
+            ]
+        );
+
+        let mut pixels = vec![0x01, 0x02, 0x03];
+        PngCgbi::normalize(&mut pixels, 3);
+        assert_eq!(pixels, vec![0x03, 0x02, 0x01]);
+    }
+}
@@ -0,0 +1,735 @@
+use std::{borrow::Cow, ops::Range};
+
+use anyhow::Result;
+use flate2::{Decompress, FlushDecompress, Status};
+
+use crate::{crc, error::Error};
+
+pub const SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+
+/// Inflated bytes kept past the expected scanlines, so data hidden after them can be reported.
+pub const EXTRA: usize = 1 << 20;
+
+/// Inflated bytes kept for a compressed text chunk or profile.
+const TEXT_LIMIT: usize = 1 << 20;
+
+pub const KNOWN_CHUNKS: [&[u8; 4]; 31] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"tRNS", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB",
+    b"cICP", b"mDCV", b"cLLI", b"tEXt", b"zTXt", b"iTXt", b"bKGD", b"hIST", b"pHYs", b"sPLT",
+    b"eXIf", b"tIME", b"acTL", b"fcTL", b"fdAT", b"oFFs", b"pCAL", b"sCAL", b"sTER", b"gIFg",
+    b"gIFx",
+];
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Chunk<'a> {
+    pub offset: usize,
+    pub kind: [u8; 4],
+    pub data: &'a [u8],
+    pub crc: u32,
+}
+
+impl Chunk<'_> {
+    pub fn name(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.kind)
+    }
+
+    pub fn computed_crc(&self) -> u32 {
+        chunk_crc(&self.kind, self.data)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.crc == self.computed_crc()
+    }
+
+    pub fn is_known(&self) -> bool {
+        KNOWN_CHUNKS.contains(&&self.kind)
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.kind[1].is_ascii_lowercase()
+    }
+
+    /// Decodes the text and metadata chunks into a readable summary.
+    pub fn describe(&self) -> Option<String> {
+        let data = self.data;
+        let description = match &self.kind {
+            b"tEXt" => {
+                let (keyword, text) = split_null(data)?;
+                format!("{:?}={:?}", latin1(keyword), latin1(text))
+            }
+            b"zTXt" => {
+                let (keyword, rest) = split_null(data)?;
+                let text = inflate(rest.get(1..)?, true, TEXT_LIMIT).data;
+                format!("{:?}={:?}", latin1(keyword), latin1(&text))
+            }
+            b"iTXt" => {
+                let (keyword, rest) = split_null(data)?;
+                let (compressed, rest) = (*rest.first()? != 0, rest.get(2..)?);
+                let (language, rest) = split_null(rest)?;
+                let (translated, text) = split_null(rest)?;
+                let text = match compressed {
+                    true => inflate(text, true, TEXT_LIMIT).data,
+                    false => text.to_vec(),
+                };
+                format!(
+                    "{:?}={:?}, language={:?}, translated={:?}",
+                    latin1(keyword),
+                    String::from_utf8_lossy(&text),
+                    latin1(language),
+                    String::from_utf8_lossy(translated),
+                )
+            }
+            b"eXIf" => {
+                let order = match data.get(..2)? {
+                    b"II" => "little endian",
+                    b"MM" => "big endian",
+                    _ => "unknown byte order",
+                };
+                format!("{} bytes, {}", data.len(), order)
+            }
+            b"tIME" if data.len() == 7 => format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                u16::from_be_bytes([data[0], data[1]]),
+                data[2],
+                data[3],
+                data[4],
+                data[5],
+                data[6],
+            ),
+            b"pHYs" if data.len() == 9 => format!(
+                "x={}, y={}, unit={}",
+                u32::from_be_bytes(data[0..4].try_into().unwrap()),
+                u32::from_be_bytes(data[4..8].try_into().unwrap()),
+                if data[8] == 1 { "meter" } else { "unknown" },
+            ),
+            b"gAMA" if data.len() == 4 => format!(
+                "gamma={}",
+                u32::from_be_bytes(data.try_into().unwrap()) as f64 / 100000.0
+            ),
+            b"sRGB" if data.len() == 1 => {
+                let intent = match data[0] {
+                    0 => "perceptual",
+                    1 => "relative colorimetric",
+                    2 => "saturation",
+                    3 => "absolute colorimetric",
+                    _ => "unknown",
+                };
+                format!("intent={}", intent)
+            }
+            b"iCCP" => {
+                let (name, rest) = split_null(data)?;
+                let profile = inflate(rest.get(1..)?, true, TEXT_LIMIT).data;
+                format!("profile={:?}, {} bytes", latin1(name), profile.len())
+            }
+            _ => return None,
+        };
+
+        Some(description)
+    }
+
+    fn is_plausible(&self) -> bool {
+        self.kind.iter().all(u8::is_ascii_alphabetic)
+    }
+
+    pub fn data_offset(&self) -> usize {
+        self.offset + 8
+    }
+
+    pub fn crc_offset(&self) -> usize {
+        self.data_offset() + self.data.len()
+    }
+
+    pub fn end(&self) -> usize {
+        self.crc_offset() + 4
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub compression: u8,
+    pub filter: u8,
+    pub interlace: u8,
+}
+
+impl Header {
+    pub fn from(chunk: &Chunk) -> Result<Self, Error> {
+        let data = chunk.data;
+        if &chunk.kind != b"IHDR" || data.len() != 13 {
+            return Err(Error::PngIhdr);
+        }
+
+        Ok(Self {
+            width: u32::from_be_bytes(data[0..4].try_into().unwrap()),
+            height: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            bit_depth: data[8],
+            color_type: data[9],
+            compression: data[10],
+            filter: data[11],
+            interlace: data[12],
+        })
+    }
+
+    pub fn bits_per_pixel(&self) -> Result<u32, Error> {
+        bits_per_pixel(self.bit_depth, self.color_type).ok_or(Error::PngIhdr)
+    }
+
+    /// Lists the (width, height) of each pass, a single one unless Adam7 interlaced.
+    pub fn passes(&self) -> Vec<(u32, u32)> {
+        const ADAM7: [(u32, u32, u32, u32); 7] = [
+            (0, 0, 8, 8),
+            (4, 0, 8, 8),
+            (0, 4, 4, 8),
+            (2, 0, 4, 4),
+            (0, 2, 2, 4),
+            (1, 0, 2, 2),
+            (0, 1, 1, 2),
+        ];
+
+        if self.interlace == 0 {
+            return vec![(self.width, self.height)];
+        }
+        ADAM7
+            .iter()
+            .map(|&(x, y, dx, dy)| {
+                let width = self.width.saturating_sub(x).div_ceil(dx);
+                let height = self.height.saturating_sub(y).div_ceil(dy);
+                (width, height)
+            })
+            .collect()
+    }
+
+    /// Bytes of filtered scanlines over every pass, which is what the IDAT stream inflates to.
+    pub fn raw_size(&self) -> Result<usize, Error> {
+        let bits = self.bits_per_pixel()? as usize;
+        self.passes()
+            .into_iter()
+            .filter(|&(width, height)| width > 0 && height > 0)
+            .try_fold(0_usize, |size, (width, height)| {
+                let stride = (width as usize).checked_mul(bits)?.div_ceil(8) + 1;
+                size.checked_add(stride.checked_mul(height as usize)?)
+            })
+            .ok_or(Error::PngIhdr)
+    }
+}
+
+#[derive(Debug)]
+pub struct Png<'a> {
+    pub chunks: Vec<Chunk<'a>>,
+    pub gaps: Vec<Range<usize>>,
+    pub end: usize,
+}
+
+impl<'a> Png<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if !data.starts_with(&SIGNATURE) {
+            return Err(Error::PngSignature.into());
+        }
+
+        let mut chunks = Vec::new();
+        let mut gaps = Vec::new();
+        let mut offset = SIGNATURE.len();
+        loop {
+            let chunk = match Self::chunk_at(data, offset).filter(Chunk::is_plausible) {
+                Some(chunk) => chunk,
+                None => {
+                    let Some(chunk) = Self::resync(data, offset) else {
+                        break;
+                    };
+                    gaps.push(offset..chunk.offset);
+                    chunk
+                }
+            };
+            offset = chunk.end();
+            let is_end = &chunk.kind == b"IEND";
+            chunks.push(chunk);
+            if is_end {
+                break;
+            }
+        }
+
+        Ok(Self {
+            chunks,
+            gaps,
+            end: offset,
+        })
+    }
+
+    /// Finds the next chunk after `offset` whose type and CRC are both intact.
+    fn resync(data: &'a [u8], offset: usize) -> Option<Chunk<'a>> {
+        (offset + 1..data.len())
+            .flat_map(|x| Self::chunk_at(data, x))
+            .find(|x| x.is_plausible() && x.is_valid())
+    }
+
+    fn chunk_at(data: &[u8], offset: usize) -> Option<Chunk<'_>> {
+        let header = data.get(offset..offset + 8)?;
+        let length = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let data_offset = offset + 8;
+        let crc_offset = data_offset.checked_add(length)?;
+        let crc = data.get(crc_offset..crc_offset + 4)?;
+
+        Some(Chunk {
+            offset,
+            kind: header[4..].try_into().unwrap(),
+            data: &data[data_offset..crc_offset],
+            crc: u32::from_be_bytes(crc.try_into().unwrap()),
+        })
+    }
+
+    /// Restores the signature and canonical chunk names by following the chunk lengths.
+    pub fn repair(data: &[u8]) -> Vec<u8> {
+        let mut fixed = data.to_vec();
+        // The IHDR length sits at offset 0 instead of 8 when the signature is missing, whatever
+        // is left of the chunk name.
+        let ihdr_length = Some(&[0, 0, 0, 13][..]);
+        let stripped = data.get(..4) == ihdr_length && data.get(8..12) != ihdr_length;
+        if stripped {
+            fixed.splice(..0, SIGNATURE);
+        } else if fixed.len() >= SIGNATURE.len() {
+            fixed[..SIGNATURE.len()].copy_from_slice(&SIGNATURE);
+        }
+
+        let mut offset = SIGNATURE.len();
+        let mut index = 0;
+        while let Some(chunk) = Png::chunk_at(&fixed, offset) {
+            let is_last = chunk.end() + 12 > fixed.len();
+            let kind = Self::canonical_kind(&chunk, index, is_last);
+            let end = chunk.end();
+            let is_end = kind.unwrap_or(&chunk.kind) == b"IEND";
+            if let Some(kind) = kind {
+                fixed[offset + 4..offset + 8].copy_from_slice(kind);
+            }
+            if is_end {
+                break;
+            }
+
+            offset = end;
+            index += 1;
+        }
+
+        fixed
+    }
+
+    fn canonical_kind(chunk: &Chunk, index: usize, is_last: bool) -> Option<&'static [u8; 4]> {
+        if chunk.is_known() {
+            return None;
+        }
+
+        let mut sorted = chunk.kind;
+        sorted.sort_unstable();
+        let permutations = KNOWN_CHUNKS
+            .iter()
+            .filter(|x| {
+                let mut known = ***x;
+                known.sort_unstable();
+                known == sorted
+            })
+            .collect::<Vec<_>>();
+
+        KNOWN_CHUNKS
+            .iter()
+            .find(|x| {
+                Chunk {
+                    kind: ***x,
+                    ..chunk.clone()
+                }
+                .is_valid()
+            })
+            .or_else(|| {
+                KNOWN_CHUNKS
+                    .iter()
+                    .find(|x| x.eq_ignore_ascii_case(&chunk.kind))
+            })
+            .or(match permutations.as_slice() {
+                [x] => Some(*x),
+                _ => None,
+            })
+            .or_else(|| match (index, chunk.data.len()) {
+                (0, 13) => Some(&KNOWN_CHUNKS[0]),
+                (_, 0) if is_last => Some(&KNOWN_CHUNKS[3]),
+                _ => None,
+            })
+            .copied()
+    }
+
+    pub fn chunk(&self, kind: &[u8; 4]) -> Option<&Chunk<'a>> {
+        self.chunks.iter().find(|x| &x.kind == kind)
+    }
+
+    pub fn header(&self) -> Result<Header, Error> {
+        self.chunk(b"IHDR")
+            .ok_or(Error::PngIhdr)
+            .and_then(Header::from)
+    }
+
+    pub fn idat(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|x| &x.kind == b"IDAT")
+            .flat_map(|x| x.data)
+            .copied()
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Inflated {
+    pub data: Vec<u8>,
+    pub consumed: usize,
+    pub complete: bool,
+    /// The output reached the limit before the stream ended.
+    pub truncated: bool,
+}
+
+/// CRC32 over the chunk type and data, on the same engine the IHDR search uses.
+fn chunk_crc(kind: &[u8; 4], data: &[u8]) -> u32 {
+    !crc::CRC32.update_slice(crc::CRC32.update_slice(!0, kind), data)
+}
+
+pub fn encode_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(data.len() + 12);
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(kind);
+    bytes.extend_from_slice(data);
+    bytes.extend_from_slice(&chunk_crc(kind, data).to_be_bytes());
+    bytes
+}
+
+/// Inflates as much of `data` as possible up to `limit` bytes, keeping the output of a
+/// damaged stream.
+pub fn inflate(data: &[u8], zlib_header: bool, limit: usize) -> Inflated {
+    let mut decompress = Decompress::new(zlib_header);
+    let mut out = Vec::with_capacity(data.len().saturating_mul(4).min(limit));
+    let complete = loop {
+        if out.len() == out.capacity() {
+            out.reserve_exact(out.len().max(1 << 16).min(limit.saturating_sub(out.len())));
+        }
+
+        let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+        let input = &data[total_in as usize..];
+        match decompress.decompress_vec(input, &mut out, FlushDecompress::None) {
+            Ok(Status::StreamEnd) => break true,
+            Ok(_) if decompress.total_in() != total_in || decompress.total_out() != total_out => (),
+            _ => break false,
+        }
+    };
+
+    let truncated = !complete && out.len() >= limit;
+    out.truncate(limit);
+    Inflated {
+        data: out,
+        consumed: decompress.total_in() as usize,
+        complete,
+        truncated,
+    }
+}
+
+/// Reverses the scanline filters of `height` rows, returning the pixels and each row's filter type.
+pub fn unfilter(raw: &[u8], width: u32, height: u32, bits: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+    // The dimensions are untrusted, so a size that overflows is reported as unsatisfiable.
+    let stride = (width as usize)
+        .checked_mul(bits as usize)
+        .ok_or(Error::PngIdat(raw.len(), usize::MAX))?
+        .div_ceil(8);
+    let bpp = (bits as usize).div_ceil(8);
+    let size = (stride + 1)
+        .checked_mul(height as usize)
+        .ok_or(Error::PngIdat(raw.len(), usize::MAX))?;
+    if raw.len() < size {
+        return Err(Error::PngIdat(raw.len(), size).into());
+    }
+
+    let mut pixels = vec![0_u8; stride * height as usize];
+    let mut filters = Vec::with_capacity(height as usize);
+    for (y, line) in raw[..size].chunks_exact(stride + 1).enumerate() {
+        let (filter, line) = (line[0], &line[1..]);
+        let (done, rest) = pixels.split_at_mut(y * stride);
+        let prev = done
+            .get(done.len().saturating_sub(stride)..)
+            .filter(|_| y > 0);
+        let curr = &mut rest[..stride];
+        for x in 0..stride {
+            let a = if x >= bpp { curr[x - bpp] } else { 0 };
+            let b = prev.map_or(0, |p| p[x]);
+            let c = match (x >= bpp, prev) {
+                (true, Some(p)) => p[x - bpp],
+                _ => 0,
+            };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(Error::PngFilter(y, filter).into()),
+            };
+            curr[x] = line[x].wrapping_add(predictor);
+        }
+        filters.push(filter);
+    }
+
+    Ok((pixels, filters))
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn split_null(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let i = data.iter().position(|&x| x == 0)?;
+    Some((&data[..i], &data[i + 1..]))
+}
+
+fn latin1(data: &[u8]) -> String {
+    data.iter().map(|&x| x as char).collect()
+}
+
+pub fn bits_per_pixel(bit_depth: u8, color_type: u8) -> Option<u32> {
+    let channels = match (color_type, bit_depth) {
+        (0, 1 | 2 | 4 | 8 | 16) => 1,
+        (2, 8 | 16) => 3,
+        (3, 1 | 2 | 4 | 8) => 1,
+        (4, 8 | 16) => 2,
+        (6, 8 | 16) => 4,
+        _ => return None,
+    };
+
+    Some(channels * bit_depth as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::{bits_per_pixel, encode_chunk, inflate, unfilter, Header, Png, SIGNATURE};
+
+    #[test]
+    fn test_parse() {
+        let mut data = SIGNATURE.to_vec();
+        data.extend(encode_chunk(
+            b"IHDR",
+            &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0],
+        ));
+        data.extend(encode_chunk(b"tEXt", b"Comment\0flag"));
+        data.extend(encode_chunk(b"IEND", &[]));
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+
+        let png = Png::parse(&data).unwrap();
+        let kinds = png.chunks.iter().map(|x| x.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![*b"IHDR", *b"tEXt", *b"IEND"]);
+        assert_eq!(png.chunks[1].offset, 0x21);
+        assert_eq!(png.chunks[1].data, b"Comment\0flag");
+        assert!(png.chunks[1].is_valid());
+        assert!(!png.chunks[2].is_valid());
+        assert_eq!(png.end, data.len());
+    }
+
+    #[test]
+    fn test_parse_gaps() {
+        let mut data = SIGNATURE.to_vec();
+        data.extend(encode_chunk(
+            b"IHDR",
+            &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0],
+        ));
+        data.extend_from_slice(b"PK\x03\x04hidden");
+        data.extend(encode_chunk(b"prIv", b"secret"));
+        data.extend(encode_chunk(b"IEND", &[]));
+        let end = data.len();
+        data.extend_from_slice(b"trailing");
+
+        let png = Png::parse(&data).unwrap();
+        let kinds = png.chunks.iter().map(|x| x.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![*b"IHDR", *b"prIv", *b"IEND"]);
+        assert_eq!(png.gaps, vec![0x21..0x2b]);
+        assert!(!png.chunks[1].is_known());
+        assert!(png.chunks[1].is_private());
+        assert_eq!(png.end, end);
+    }
+
+    #[test]
+    fn test_repair() {
+        let mut data = SIGNATURE.to_vec();
+        data.extend(encode_chunk(
+            b"IHDR",
+            &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0],
+        ));
+        data.extend(encode_chunk(b"IDAT", &[0; 16]));
+        data.extend(encode_chunk(b"tEXt", b"a\0b"));
+        data.extend(encode_chunk(b"IEND", &[]));
+
+        let mut broken = data.clone();
+        broken[1..4].copy_from_slice(b"png");
+        broken[12..16].copy_from_slice(b"ihdr");
+        broken[0x25..0x29].copy_from_slice(b"IDTA");
+        broken[0x41..0x45].copy_from_slice(b"xxxx");
+        let last = broken.len() - 8;
+        broken[last..last + 4].copy_from_slice(b"iend");
+        assert!(Png::parse(&broken).is_err());
+        assert_eq!(Png::repair(&broken), data);
+        assert_eq!(Png::repair(&broken[8..]), data);
+
+        broken[12..16].copy_from_slice(b"\0x\x7f~");
+        assert_eq!(Png::repair(&broken[8..]), data);
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let mut data = SIGNATURE.to_vec();
+        data.extend(encode_chunk(
+            b"IHDR",
+            &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0],
+        ));
+        data.extend(encode_chunk(b"IDAT", &[0; 16]));
+        data.truncate(data.len() - 6);
+
+        let png = Png::parse(&data).unwrap();
+        assert_eq!(png.chunks.len(), 1);
+        assert_eq!(png.end, 0x21);
+        assert!(Png::parse(&data[1..]).is_err());
+    }
+
+    #[test]
+    fn test_describe() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"flag{ztxt}").unwrap();
+        let mut ztxt = b"Comment\0\0".to_vec();
+        ztxt.extend(encoder.finish().unwrap());
+
+        let data = [
+            encode_chunk(b"tEXt", b"Title\0caf\xe9"),
+            encode_chunk(b"zTXt", &ztxt),
+            encode_chunk(b"iTXt", b"Author\0\0\0en\0\0flag{itxt}"),
+            encode_chunk(b"tIME", &[0x07, 0xe8, 3, 14, 13, 9, 56]),
+            encode_chunk(b"pHYs", &[0, 0, 0x0b, 0x13, 0, 0, 0x0b, 0x13, 1]),
+            encode_chunk(b"gAMA", &[0, 0, 0xb1, 0x8f]),
+            encode_chunk(b"IDAT", &[]),
+        ]
+        .concat();
+        let descriptions = Png::parse(&[SIGNATURE.as_slice(), &data].concat())
+            .unwrap()
+            .chunks
+            .iter()
+            .map(|x| x.describe())
+            .collect::<Vec<_>>();
+
+        assert_eq!(descriptions[0].as_deref(), Some(r#""Title"="café""#));
+        assert_eq!(
+            descriptions[1].as_deref(),
+            Some(r#""Comment"="flag{ztxt}""#)
+        );
+        assert_eq!(
+            descriptions[2].as_deref(),
+            Some(r#""Author"="flag{itxt}", language="en", translated="""#)
+        );
+        assert_eq!(descriptions[3].as_deref(), Some("2024-03-14 13:09:56"));
+        assert_eq!(
+            descriptions[4].as_deref(),
+            Some("x=2835, y=2835, unit=meter")
+        );
+        assert_eq!(descriptions[5].as_deref(), Some("gamma=0.45455"));
+        assert_eq!(descriptions[6], None);
+    }
+
+    #[test]
+    fn test_inflate() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[7; 1000]).unwrap();
+        let mut data = encoder.finish().unwrap();
+        let len = data.len();
+        data.extend_from_slice(b"tail");
+
+        let inflated = inflate(&data, true, 1000);
+        assert!(inflated.complete && !inflated.truncated);
+        assert_eq!(inflated.data, vec![7; 1000]);
+        assert_eq!(inflated.consumed, len);
+
+        let inflated = inflate(&data[..len / 2], true, 1000);
+        assert!(!inflated.complete && !inflated.truncated);
+
+        let inflated = inflate(&data, true, 600);
+        assert!(!inflated.complete && inflated.truncated);
+        assert_eq!(inflated.data, vec![7; 600]);
+    }
+
+    #[test]
+    fn test_unfilter() {
+        let raw = [
+            0, 1, 2, 3, 4, //
+            1, 1, 1, 1, 1, //
+            2, 1, 1, 1, 1, //
+            3, 0, 0, 0, 0, //
+            4, 1, 0, 0, 0,
+        ];
+        let (pixels, filters) = unfilter(&raw, 2, 5, 16).unwrap();
+        assert_eq!(filters, vec![0, 1, 2, 3, 4]);
+        assert_eq!(
+            pixels,
+            vec![
+                1, 2, 3, 4, //
+                1, 1, 2, 2, //
+                2, 2, 3, 3, //
+                1, 1, 2, 2, //
+                2, 1, 2, 2,
+            ]
+        );
+        assert!(unfilter(&raw, 2, 6, 16).is_err());
+        assert!(unfilter(&[5, 0], 1, 1, 8).is_err());
+        assert!(unfilter(&raw, u32::MAX, u32::MAX, 64).is_err());
+    }
+
+    #[test]
+    fn test_passes() {
+        let mut header = Header {
+            width: 5,
+            height: 3,
+            bit_depth: 8,
+            color_type: 0,
+            compression: 0,
+            filter: 0,
+            interlace: 0,
+        };
+        assert_eq!(header.passes(), vec![(5, 3)]);
+        assert_eq!(header.raw_size().unwrap(), 18);
+
+        header.interlace = 1;
+        assert_eq!(
+            header.passes(),
+            vec![(1, 1), (1, 1), (2, 0), (1, 1), (3, 1), (2, 2), (5, 1)]
+        );
+        assert_eq!(header.raw_size().unwrap(), 22);
+
+        header.width = u32::MAX;
+        header.height = u32::MAX;
+        header.bit_depth = 16;
+        header.color_type = 6;
+        assert!(header.raw_size().is_err());
+    }
+
+    #[test]
+    fn test_bits_per_pixel() {
+        assert_eq!(bits_per_pixel(8, 2), Some(24));
+        assert_eq!(bits_per_pixel(16, 6), Some(64));
+        assert_eq!(bits_per_pixel(4, 3), Some(4));
+        assert_eq!(bits_per_pixel(16, 3), None);
+        assert_eq!(bits_per_pixel(8, 5), None);
+    }
+}
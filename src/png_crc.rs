@@ -1,28 +1,34 @@
-use std::{
-    path::PathBuf,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
-};
+use std::{collections::HashMap, io::Cursor, num::NonZeroUsize, path::PathBuf, sync::Arc, thread};
 
 use anyhow::Result;
 use async_trait::async_trait;
-use crc32fast::Hasher;
-use tokio::{fs, spawn};
+use png::Decoder;
+use tokio::{fs, task};
 use tracing::instrument;
 
-use crate::Command;
+use crate::{
+    crc,
+    error::Error,
+    png_chunk::{self, Chunk, Header, Png},
+    Command,
+};
 
 #[derive(Debug)]
 pub struct PngCrc {
     file: String,
+    mode: PngCrcMode,
+    max: u32,
 }
 
-#[derive(Copy, PartialEq, Eq, Clone, Debug)]
-enum WoH {
-    Width,
-    Height,
+/// What to fix, by default the width and height are brute forced against the IHDR CRC.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PngCrcMode {
+    Dimensions,
+    FixCrc,
+    FromIdat,
+    Others,
+    Carve,
+    Repair,
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -35,58 +41,145 @@ struct IHDR {
 }
 
 impl IHDR {
-    fn from(data: &[u8]) -> (Self, u32) {
+    fn from(chunk: &Chunk) -> Result<(Self, u32), Error> {
+        let header = Header::from(chunk)?;
         let ihdr = Self {
-            header: data[12..16].try_into().unwrap(),
-            width: data[16..20].try_into().unwrap(),
-            height: data[20..24].try_into().unwrap(),
-            others: data[24..29].try_into().unwrap(),
+            header: chunk.kind,
+            width: header.width.to_be_bytes(),
+            height: header.height.to_be_bytes(),
+            others: [
+                header.bit_depth,
+                header.color_type,
+                header.compression,
+                header.filter,
+                header.interlace,
+            ],
         };
-        let crc = Self::parse_crc(data);
 
-        (ihdr, crc)
+        Ok((ihdr, chunk.crc))
     }
 
-    fn parse_crc(data: &[u8]) -> u32 {
-        let bytes = &data[29..33];
-        u32::from_be_bytes(bytes.try_into().unwrap())
+    /// The parsed fields, for the checks [`Header`] already knows.
+    fn fields(&self) -> Header {
+        let [bit_depth, color_type, compression, filter, interlace] = self.others;
+        Header {
+            width: self.width(),
+            height: self.height(),
+            bit_depth,
+            color_type,
+            compression,
+            filter,
+            interlace,
+        }
     }
 
-    #[instrument(skip(self, finished))]
-    async fn brute(&self, woh: WoH, expected: u32, finished: Arc<AtomicBool>) -> (WoH, u32) {
-        for i in 0_u32.. {
-            let bytes = i.to_be_bytes();
-            let computed = self.crc(Some((woh, bytes)));
-            if finished.load(Ordering::SeqCst) || computed == expected {
-                tracing::trace!(?woh, i, computed);
+    /// Searches every (width, height) in `1..=max` for the pairs matching `expected`.
+    ///
+    /// CRC32 is affine, so `crc(w, h) == crc(w, 0) ^ crc(0, h) ^ crc(0, 0)` and the joint
+    /// search only needs one table of heights shared by the worker threads.
+    #[instrument(skip(self))]
+    fn brute(&self, max: u32, expected: u32) -> Vec<(u32, u32)> {
+        let zero = [0; 4];
+        let base = self.crc_with(zero, zero);
+        let mut heights = HashMap::<u32, Vec<u32>>::with_capacity(max as usize);
+        for height in 1..=max {
+            let crc = self.crc_with(zero, height.to_be_bytes());
+            heights.entry(crc).or_default().push(height);
+        }
+
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let mut found = thread::scope(|s| {
+            let heights = &heights;
+            let handles = (0..threads)
+                .map(|i| {
+                    s.spawn(move || {
+                        let mut found = Vec::new();
+                        for width in (1..=max).skip(i).step_by(threads) {
+                            let crc = self.crc_with(width.to_be_bytes(), zero) ^ base ^ expected;
+                            if let Some(hs) = heights.get(&crc) {
+                                found.extend(hs.iter().map(|&height| (width, height)));
+                            }
+                        }
+                        found
+                    })
+                })
+                .collect::<Vec<_>>();
 
-                finished.store(true, Ordering::SeqCst);
-                return (woh, i);
-            }
+            handles
+                .into_iter()
+                .flat_map(|x| x.join().expect("Failed to join brute thread."))
+                .collect::<Vec<_>>()
+        });
+        found.sort_unstable();
+        tracing::trace!(?found);
+
+        found
+    }
+
+    /// Lists every valid (bit depth, color type, interlace) combination as `others` bytes.
+    fn others_candidates() -> Vec<[u8; 5]> {
+        let color_types: [(u8, &[u8]); 5] = [
+            (0, &[1, 2, 4, 8, 16]),
+            (2, &[8, 16]),
+            (3, &[1, 2, 4, 8]),
+            (4, &[8, 16]),
+            (6, &[8, 16]),
+        ];
+
+        color_types
+            .into_iter()
+            .flat_map(|(color_type, bit_depths)| {
+                bit_depths.iter().flat_map(move |&bit_depth| {
+                    [0, 1].map(|interlace| [bit_depth, color_type, 0, 0, interlace])
+                })
+            })
+            .collect()
+    }
+
+    fn with_others(&self, others: [u8; 5]) -> Self {
+        Self {
+            others,
+            ..self.clone()
         }
+    }
+
+    fn crc(&self) -> u32 {
+        self.crc_with(self.width, self.height)
+    }
 
-        unreachable!()
+    fn crc_with(&self, width: [u8; 4], height: [u8; 4]) -> u32 {
+        let crc = [&self.header[..], &width, &height, &self.others]
+            .iter()
+            .fold(!0, |crc, x| crc::CRC32.update_slice(crc, x));
+        !crc
     }
 
-    fn crc(&self, woh: Option<(WoH, [u8; 4])>) -> u32 {
-        let mut hasher = Hasher::new();
-        hasher.update(&self.header);
-        match woh {
-            Some((WoH::Width, width)) => {
-                hasher.update(&width);
-                hasher.update(&self.height);
+    /// Lists the (width, height) pairs whose scanlines tile `raw` and start with valid filter bytes.
+    fn dimensions_from_idat(&self, raw: &[u8]) -> Result<Vec<(u32, u32)>> {
+        let header = self.fields();
+        let bits = header.bits_per_pixel()?;
+        if header.interlace != 0 {
+            return Err(anyhow::anyhow!("Interlaced png is not supported."));
+        }
+
+        let mut dimensions = Vec::new();
+        for width in 1_u32.. {
+            let stride = (width as usize * bits as usize).div_ceil(8) + 1;
+            if stride > raw.len() {
+                break;
             }
-            Some((WoH::Height, height)) => {
-                hasher.update(&self.width);
-                hasher.update(&height);
+            if !raw.len().is_multiple_of(stride) {
+                continue;
             }
-            None => {
-                hasher.update(&self.width);
-                hasher.update(&self.height);
+
+            if raw.chunks_exact(stride).all(|x| x[0] <= 4) {
+                let height = (raw.len() / stride) as u32;
+                tracing::trace!(width, height);
+                dimensions.push((width, height));
             }
         }
-        hasher.update(&self.others);
-        hasher.finalize()
+
+        Ok(dimensions)
     }
 
     fn width(&self) -> u32 {
@@ -101,10 +194,46 @@ impl IHDR {
 #[async_trait]
 impl Command for PngCrc {
     async fn execute(self: Box<Self>) -> Result<()> {
-        let Self { file } = *self;
+        let Self { file, mode, max } = *self;
 
         let mut data = fs::read(&file).await?;
-        let (ihdr, expected) = IHDR::from(&data);
+        if mode == PngCrcMode::Repair {
+            return Self::repair(&file, data).await;
+        }
+
+        let (ihdr, expected, fixes, idat, offsets) = {
+            let png = Png::parse(&data)?;
+            Self::report(&png, data.len());
+            if mode == PngCrcMode::Carve {
+                return Self::carve(&file, &data, &png).await;
+            }
+
+            let fixes = png
+                .chunks
+                .iter()
+                .filter(|x| !x.is_valid())
+                .map(|x| (x.name().into_owned(), x.crc_offset(), x.computed_crc()))
+                .collect::<Vec<_>>();
+            let chunk = png.chunks.first().ok_or(Error::PngIhdr)?;
+            let (ihdr, expected) = IHDR::from(chunk)?;
+            // A resync can move the IHDR, so fields are written relative to where it was found.
+            let offsets = (chunk.data_offset(), chunk.crc_offset());
+            (ihdr, expected, fixes, png.idat(), offsets)
+        };
+
+        if mode == PngCrcMode::FixCrc {
+            if fixes.is_empty() {
+                tracing::info!("All chunk CRCs are correct.");
+                return Ok(());
+            }
+
+            for (name, offset, crc) in fixes {
+                tracing::info!("Fixed {} CRC at offset({:#x}) to {:#x}.", name, offset, crc);
+                data[offset..offset + 4].copy_from_slice(&crc.to_be_bytes());
+            }
+            return Self::save(&file, data).await;
+        }
+
         tracing::info!(
             "Read png with width({:#x}), height({:#x}) and CRC({:#x}).",
             ihdr.width(),
@@ -112,32 +241,226 @@ impl Command for PngCrc {
             expected,
         );
 
-        let computed = ihdr.crc(None);
+        let computed = ihdr.crc();
         tracing::info!("Computed CRC is {:#x}.", computed);
+        if mode == PngCrcMode::FromIdat {
+            return Self::fix_from_idat(&file, data, &ihdr, expected, &idat, offsets, max).await;
+        }
+        if mode == PngCrcMode::Others {
+            return Self::fix_others(&file, data, &ihdr, expected, offsets).await;
+        }
         if computed == expected {
             return Ok(());
         }
 
         let ihdr = Arc::new(ihdr);
-        let finished = Arc::new(AtomicBool::new(false));
-        let (woh, v) = tokio::select! {
-            width = {
-                let ihdr = ihdr.clone();
-                let finished = finished.clone();
-                spawn(async move {ihdr.brute(WoH::Width, expected, finished).await})
-            } => width,
-            height = {
-                spawn(async move { ihdr.brute(WoH::Height, expected, finished).await})
-            } => height,
-        }?;
-        tracing::info!("Found correct {:?}({:#x}).", woh, v);
-
-        let bytes = v.to_be_bytes();
-        match woh {
-            WoH::Width => data[16..20].copy_from_slice(&bytes),
-            WoH::Height => data[20..24].copy_from_slice(&bytes),
+        let found = {
+            let ihdr = ihdr.clone();
+            task::spawn_blocking(move || ihdr.brute(max, expected)).await?
+        };
+        for (width, height) in &found {
+            tracing::info!("Found width({:#x}), height({:#x}).", width, height);
+        }
+
+        // Prefer the pairs where only one of width and height was changed.
+        let Some(&(width, height)) = found
+            .iter()
+            .find(|&&(w, h)| w == ihdr.width() || h == ihdr.height())
+            .or(found.first())
+        else {
+            return Err(anyhow::anyhow!(
+                "No width and height up to {:#x} match the CRC.",
+                max
+            ));
+        };
+        tracing::info!("Found correct width({:#x}), height({:#x}).", width, height);
+
+        let (data_offset, _) = offsets;
+        data[data_offset..data_offset + 4].copy_from_slice(&width.to_be_bytes());
+        data[data_offset + 4..data_offset + 8].copy_from_slice(&height.to_be_bytes());
+
+        Self::save(&file, data).await
+    }
+}
+
+impl PngCrc {
+    pub fn new(file: String, mode: PngCrcMode, max: u32) -> Self {
+        Self { file, mode, max }
+    }
+
+    async fn repair(file: &str, data: Vec<u8>) -> Result<()> {
+        let fixed = Png::repair(&data);
+        // A stripped signature shifts everything, so diff against the re-aligned input.
+        let shift = fixed.len() - data.len();
+        let mut changed = (0..fixed.len())
+            .filter(|&i| i < shift || data[i - shift] != fixed[i])
+            .peekable();
+        if changed.peek().is_none() {
+            tracing::info!("Signature and chunk names are intact.");
+            return Ok(());
+        }
+
+        for i in changed {
+            let old = i.checked_sub(shift).map(|x| data[x]);
+            match old {
+                Some(old) => tracing::info!("offset({:#x}): {:#04x} -> {:#04x}", i, old, fixed[i]),
+                None => tracing::info!("offset({:#x}): inserted {:#04x}", i, fixed[i]),
+            }
+        }
+
+        Self::report(&Png::parse(&fixed)?, fixed.len());
+        Self::save(file, fixed).await
+    }
+
+    async fn carve(file: &str, data: &[u8], png: &Png<'_>) -> Result<()> {
+        let chunks = png
+            .chunks
+            .iter()
+            .filter(|x| !x.is_known())
+            .map(|x| (x.data_offset(), x.data));
+        let gaps = png.gaps.iter().map(|x| (x.start, &data[x.clone()]));
+        let trailing = Some((png.end, &data[png.end..])).filter(|x| !x.1.is_empty());
+
+        let stem = PathBuf::from(file)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap()
+            .to_owned();
+        for (offset, bytes) in chunks.chain(gaps).chain(trailing) {
+            let extension = infer::get(bytes).map_or("bin", |x| x.extension());
+            let path = format!("{}-{:#x}.{}", stem, offset, extension);
+            fs::write(&path, bytes).await?;
+            tracing::info!(
+                "Carved {} bytes at offset({:#x}) as ({:?}).",
+                bytes.len(),
+                offset,
+                path
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn fix_others(
+        file: &str,
+        mut data: Vec<u8>,
+        ihdr: &IHDR,
+        expected: u32,
+        (data_offset, _): (usize, usize),
+    ) -> Result<()> {
+        let others_range = data_offset + 8..data_offset + 13;
+        let mut matched = Vec::new();
+        for others in IHDR::others_candidates() {
+            let crc = ihdr.with_others(others).crc();
+            if crc != expected {
+                continue;
+            }
+
+            data[others_range.clone()].copy_from_slice(&others);
+            let decoded = Self::decodes(&data);
+            tracing::info!(
+                "Candidate bit depth({}), color type({}), interlace({}), decoded({}).",
+                others[0],
+                others[1],
+                others[4],
+                decoded,
+            );
+            matched.push((others, decoded));
         }
 
+        let Some(&(others, _)) = matched.iter().find(|x| x.1).or(matched.first()) else {
+            return Err(anyhow::anyhow!("No IHDR fields match the CRC."));
+        };
+        tracing::info!(
+            "Found correct bit depth({}), color type({}), interlace({}).",
+            others[0],
+            others[1],
+            others[4],
+        );
+
+        data[others_range.clone()].copy_from_slice(&others);
+        Self::save(file, data).await
+    }
+
+    fn decodes(data: &[u8]) -> bool {
+        let mut decoder = Decoder::new(Cursor::new(data));
+        decoder.ignore_checksums(true);
+        let Ok(mut reader) = decoder.read_info() else {
+            return false;
+        };
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).is_ok()
+    }
+
+    async fn fix_from_idat(
+        file: &str,
+        mut data: Vec<u8>,
+        ihdr: &IHDR,
+        expected: u32,
+        idat: &[u8],
+        (data_offset, crc_offset): (usize, usize),
+        max: u32,
+    ) -> Result<()> {
+        // The dimensions are what is being recovered, so the stream is bounded by `max` instead.
+        let limit = Header {
+            width: max,
+            height: max,
+            ..ihdr.fields()
+        }
+        .raw_size()?;
+        let inflated = png_chunk::inflate(idat, true, limit);
+        tracing::info!(
+            "Inflated {} bytes from {}/{} bytes of IDAT, complete({}).",
+            inflated.data.len(),
+            inflated.consumed,
+            idat.len(),
+            inflated.complete,
+        );
+        if inflated.truncated {
+            tracing::warn!(
+                "Stopped inflating at {} bytes, past a {}x{} image.",
+                limit,
+                max,
+                max
+            );
+        }
+
+        let dimensions = ihdr.dimensions_from_idat(&inflated.data)?;
+        let mut matched = Vec::new();
+        for &(width, height) in &dimensions {
+            let crc = ihdr.crc_with(width.to_be_bytes(), height.to_be_bytes());
+            let is_match = crc == expected;
+            tracing::info!(
+                "Candidate width({:#x}), height({:#x}), CRC({:#x}), matched({}).",
+                width,
+                height,
+                crc,
+                is_match,
+            );
+            if is_match {
+                matched.push((width, height));
+            }
+        }
+
+        let (width, height) = match (matched.as_slice(), dimensions.as_slice()) {
+            ([x, ..], _) | ([], [x]) => *x,
+            ([], []) => return Err(anyhow::anyhow!("No dimensions fit the IDAT stream.")),
+            ([], _) => {
+                tracing::info!("Several dimensions fit and none matches the CRC, nothing saved.");
+                return Ok(());
+            }
+        };
+        tracing::info!("Found correct width({:#x}), height({:#x}).", width, height);
+
+        data[data_offset..data_offset + 4].copy_from_slice(&width.to_be_bytes());
+        data[data_offset + 4..data_offset + 8].copy_from_slice(&height.to_be_bytes());
+        let crc = ihdr.crc_with(width.to_be_bytes(), height.to_be_bytes());
+        data[crc_offset..crc_offset + 4].copy_from_slice(&crc.to_be_bytes());
+        Self::save(file, data).await
+    }
+
+    async fn save(file: &str, data: Vec<u8>) -> Result<()> {
         let png_path = PathBuf::from(file)
             .file_stem()
             .and_then(|x| x.to_str())
@@ -148,22 +471,76 @@ impl Command for PngCrc {
 
         Ok(())
     }
-}
 
-impl PngCrc {
-    pub fn new(file: String) -> Self {
-        Self { file }
+    fn report(png: &Png, len: usize) {
+        tracing::info!(
+            "{:>10} {:>4} {:>10} {:>10} {:>10} status",
+            "offset",
+            "type",
+            "length",
+            "stored",
+            "computed",
+        );
+        for chunk in &png.chunks {
+            tracing::info!(
+                "{:>#10x} {:>4} {:>10} {:#010x} {:#010x} {}",
+                chunk.offset,
+                chunk.name(),
+                chunk.data.len(),
+                chunk.crc,
+                chunk.computed_crc(),
+                if chunk.is_valid() { "ok" } else { "BAD" },
+            );
+        }
+
+        for chunk in &png.chunks {
+            if let Some(description) = chunk.describe() {
+                tracing::info!(
+                    "{} at offset({:#x}): {}",
+                    chunk.name(),
+                    chunk.offset,
+                    description
+                );
+            }
+        }
+        for chunk in png.chunks.iter().filter(|x| !x.is_known()) {
+            let private = if chunk.is_private() {
+                "private"
+            } else {
+                "public"
+            };
+            tracing::warn!(
+                "Found unknown {} chunk {} at offset({:#x}).",
+                private,
+                chunk.name(),
+                chunk.offset,
+            );
+        }
+        for gap in &png.gaps {
+            tracing::warn!(
+                "Found {} bytes between chunks at offset({:#x}).",
+                gap.len(),
+                gap.start,
+            );
+        }
+
+        if png.end < len {
+            match png.chunks.last() {
+                Some(x) if &x.kind == b"IEND" => tracing::warn!(
+                    "Found {} bytes after IEND at offset({:#x}).",
+                    len - png.end,
+                    png.end,
+                ),
+                _ => tracing::warn!("Chunk at offset({:#x}) is truncated.", png.end),
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    };
-
-    use super::{WoH, IHDR};
+    use super::IHDR;
+    use crate::png_chunk::Png;
 
     #[test]
     fn test_ihdr_from() {
@@ -172,7 +549,7 @@ mod test {
             0x44, 0x52, 0x00, 0x00, 0x01, 0x35, 0x00, 0x00, 0x04, 0x24, 0x08, 0x02, 0x00, 0x00,
             0x00, 0x93, 0xcf, 0x1e, 0xca,
         ];
-        let (ihdr, crc) = IHDR::from(&data);
+        let (ihdr, crc) = IHDR::from(&Png::parse(&data).unwrap().chunks[0]).unwrap();
         assert_eq!(
             ihdr,
             IHDR {
@@ -192,47 +569,92 @@ mod test {
             0x44, 0x52, 0x00, 0x00, 0x01, 0x35, 0x00, 0x00, 0x04, 0x24, 0x08, 0x02, 0x00, 0x00,
             0x00, 0x93, 0xcf, 0x1e, 0xca,
         ];
-        let (ihdr, crc) = IHDR::from(&data);
-        assert_eq!(ihdr.crc(None), crc);
+        let (ihdr, crc) = IHDR::from(&Png::parse(&data).unwrap().chunks[0]).unwrap();
+        assert_eq!(ihdr.crc(), crc);
+    }
+
+    #[test]
+    fn test_others_candidates() {
+        let data = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x01, 0x35, 0x00, 0x00, 0x04, 0x24, 0x10, 0x06, 0x00, 0x00,
+            0x01, 0x93, 0xcf, 0x1e, 0xca,
+        ];
+        let (ihdr, expected) = IHDR::from(&Png::parse(&data).unwrap().chunks[0]).unwrap();
+        assert_ne!(ihdr.crc(), expected);
+
+        let candidates = IHDR::others_candidates();
+        assert_eq!(candidates.len(), 30);
+        let matched = candidates
+            .into_iter()
+            .filter(|&x| ihdr.with_others(x).crc() == expected)
+            .collect::<Vec<_>>();
+        assert_eq!(matched, vec![[0x08, 0x02, 0x00, 0x00, 0x00]]);
     }
 
-    #[tokio::test]
-    async fn test_crc_brute_height() {
+    #[test]
+    fn test_dimensions_from_idat() {
+        let data = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x02, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let (ihdr, _) = IHDR::from(&Png::parse(&data).unwrap().chunks[0]).unwrap();
+        let mut raw = Vec::new();
+        for row in 0..6_u8 {
+            raw.push(row % 5);
+            raw.extend_from_slice(&[0x05; 15]);
+        }
+
+        let dimensions = ihdr.dimensions_from_idat(&raw).unwrap();
+        assert_eq!(dimensions, vec![(5, 6)]);
+    }
+
+    #[test]
+    fn test_crc_brute_height() {
         let data = [
             0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
             0x44, 0x52, 0x00, 0x00, 0x01, 0x35, 0x00, 0x00, 0x00, 0xe8, 0x08, 0x02, 0x00, 0x00,
             0x00, 0x93, 0xcf, 0x1e, 0xca,
         ];
-        let (ihdr, expected) = IHDR::from(&data);
-        let computed = ihdr.crc(None);
+        let (ihdr, expected) = IHDR::from(&Png::parse(&data).unwrap().chunks[0]).unwrap();
+        let computed = ihdr.crc();
         assert_ne!(computed, expected);
 
-        let woh = WoH::Height;
-        let finished = Arc::new(AtomicBool::new(false));
-        let (_woh, height) = ihdr.brute(woh, expected, finished.clone()).await;
-        let crc = ihdr.crc(Some((woh, height.to_be_bytes())));
-        assert_eq!(woh, _woh);
-        assert_eq!(crc, expected);
-        assert!(finished.load(Ordering::SeqCst));
+        let found = ihdr.brute(0x1000, expected);
+        assert!(found.contains(&(0x135, 0x424)));
+        for (width, height) in found {
+            let crc = ihdr.crc_with(width.to_be_bytes(), height.to_be_bytes());
+            assert_eq!(crc, expected);
+        }
     }
 
-    #[tokio::test]
-    async fn test_crc_brute_width() {
+    #[test]
+    fn test_crc_brute_width() {
         let data = [
             0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
             0x44, 0x52, 0x00, 0x00, 0x00, 0x35, 0x00, 0x00, 0x04, 0x24, 0x08, 0x02, 0x00, 0x00,
             0x00, 0x93, 0xcf, 0x1e, 0xca,
         ];
-        let (ihdr, expected) = IHDR::from(&data);
-        let computed = ihdr.crc(None);
+        let (ihdr, expected) = IHDR::from(&Png::parse(&data).unwrap().chunks[0]).unwrap();
+        let computed = ihdr.crc();
         assert_ne!(computed, expected);
 
-        let woh = WoH::Width;
-        let finished = Arc::new(AtomicBool::new(false));
-        let (_woh, height) = ihdr.brute(woh, expected, finished.clone()).await;
-        let crc = ihdr.crc(Some((woh, height.to_be_bytes())));
-        assert_eq!(woh, _woh);
-        assert_eq!(crc, expected);
-        assert!(finished.load(Ordering::SeqCst));
+        let found = ihdr.brute(0x1000, expected);
+        assert!(found.contains(&(0x135, 0x424)));
+    }
+
+    #[test]
+    fn test_crc_brute_both() {
+        let data = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x02, 0x00, 0x00,
+            0x00, 0x93, 0xcf, 0x1e, 0xca,
+        ];
+        let (ihdr, expected) = IHDR::from(&Png::parse(&data).unwrap().chunks[0]).unwrap();
+
+        let found = ihdr.brute(0x2000, expected);
+        assert!(found.contains(&(0x135, 0x424)));
+        assert!(ihdr.brute(0x100, expected).is_empty());
     }
 }
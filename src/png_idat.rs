@@ -0,0 +1,182 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::fs;
+
+use crate::{
+    error::Error,
+    png_chunk::{self, Header, Png},
+    Command,
+};
+
+#[derive(Debug)]
+pub struct PngIdat {
+    file: String,
+}
+
+#[derive(Debug)]
+struct Scanlines {
+    pixels: Vec<u8>,
+    filters: Vec<Vec<u8>>,
+    consumed: usize,
+}
+
+impl PngIdat {
+    pub fn new(file: String) -> Self {
+        Self { file }
+    }
+
+    /// Unfilters every pass in order, interlaced passes are kept apart rather than merged.
+    fn scanlines(header: &Header, raw: &[u8]) -> Result<Scanlines> {
+        let bits = header.bits_per_pixel()?;
+        let mut scanlines = Scanlines {
+            pixels: Vec::new(),
+            filters: Vec::new(),
+            consumed: 0,
+        };
+        for (width, height) in header.passes() {
+            if width == 0 || height == 0 {
+                continue;
+            }
+
+            let raw = &raw[scanlines.consumed.min(raw.len())..];
+            let (pixels, filters) = match png_chunk::unfilter(raw, width, height, bits) {
+                Err(e) if matches!(e.downcast_ref(), Some(Error::PngFilter(..))) => {
+                    let pass = scanlines.filters.len();
+                    tracing::warn!("Pass {} is left filtered ({}).", pass, e);
+                    Self::split(raw, width, height, bits)
+                }
+                x => x?,
+            };
+            scanlines.consumed += pixels.len() + filters.len();
+            scanlines.pixels.extend(pixels);
+            scanlines.filters.push(filters);
+        }
+
+        Ok(scanlines)
+    }
+
+    /// Splits the rows of a pass into its filter bytes and the still filtered pixels, for
+    /// filter types that cannot be undone.
+    fn split(raw: &[u8], width: u32, height: u32, bits: u32) -> (Vec<u8>, Vec<u8>) {
+        let stride = (width as usize * bits as usize).div_ceil(8);
+        let rows = raw[..(stride + 1) * height as usize].chunks_exact(stride + 1);
+        let filters = rows.clone().map(|x| x[0]).collect();
+        let pixels = rows.flat_map(|x| &x[1..]).copied().collect();
+
+        (pixels, filters)
+    }
+}
+
+#[async_trait]
+impl Command for PngIdat {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self { file } = *self;
+
+        let data = fs::read(&file).await?;
+        let png = Png::parse(&data)?;
+        let header = png.header()?;
+        tracing::info!(?header);
+
+        let idat = png.idat();
+        let limit = header.raw_size()?.saturating_add(png_chunk::EXTRA);
+        let inflated = png_chunk::inflate(&idat, true, limit);
+        tracing::info!(
+            "Inflated {} bytes from {}/{} bytes of IDAT, complete({}).",
+            inflated.data.len(),
+            inflated.consumed,
+            idat.len(),
+            inflated.complete,
+        );
+        if inflated.truncated {
+            tracing::warn!(
+                "Stopped inflating at {} bytes, the rest is not dumped.",
+                limit
+            );
+        }
+
+        let scanlines = Self::scanlines(&header, &inflated.data)?;
+        for (i, filters) in scanlines.filters.iter().enumerate() {
+            let rows = filters.len();
+            let filters = match filters.iter().all(|&x| x < 10) {
+                true => filters.iter().map(|&x| char::from(b'0' + x)).collect(),
+                false => hex::encode(filters),
+            };
+            tracing::info!("pass={}, rows={}, filters={}", i, rows, filters);
+        }
+
+        let stem = PathBuf::from(&file)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap()
+            .to_owned();
+        // Input left after a truncated inflate is still compressed, not trailing data.
+        let trailing = match inflated.truncated {
+            true => &[][..],
+            false => &idat[inflated.consumed..],
+        };
+        let outputs = [
+            ("idat", scanlines.pixels.as_slice()),
+            ("idat-extra", &inflated.data[scanlines.consumed..]),
+            ("idat-trailing", trailing),
+        ];
+        for (suffix, bytes) in outputs.into_iter().filter(|x| !x.1.is_empty()) {
+            let path = format!("{}-{}.bin", stem, suffix);
+            fs::write(&path, bytes).await?;
+            tracing::info!("Saved {} bytes as ({:?}).", bytes.len(), path);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PngIdat;
+    use crate::png_chunk::Header;
+
+    #[test]
+    fn test_scanlines() {
+        let header = Header {
+            width: 2,
+            height: 2,
+            bit_depth: 8,
+            color_type: 0,
+            compression: 0,
+            filter: 0,
+            interlace: 0,
+        };
+        let raw = [0, 1, 2, 1, 3, 1, 0xde, 0xad];
+
+        let scanlines = PngIdat::scanlines(&header, &raw).unwrap();
+        assert_eq!(scanlines.pixels, vec![1, 2, 3, 4]);
+        assert_eq!(scanlines.filters, vec![vec![0, 1]]);
+        assert_eq!(&raw[scanlines.consumed..], &[0xde, 0xad]);
+
+        let raw = [0, 1, 2, 7, 3, 1, 0xde];
+        let scanlines = PngIdat::scanlines(&header, &raw).unwrap();
+        assert_eq!(scanlines.pixels, vec![1, 2, 3, 1]);
+        assert_eq!(scanlines.filters, vec![vec![0, 7]]);
+        assert_eq!(scanlines.consumed, 6);
+    }
+
+    #[test]
+    fn test_scanlines_interlaced() {
+        let header = Header {
+            width: 2,
+            height: 2,
+            bit_depth: 8,
+            color_type: 0,
+            compression: 0,
+            filter: 0,
+            interlace: 1,
+        };
+        let raw = [0, 1, 0, 2, 2, 3, 4];
+
+        let scanlines = PngIdat::scanlines(&header, &raw).unwrap();
+        assert_eq!(scanlines.pixels, vec![1, 2, 3, 4]);
+        assert_eq!(scanlines.filters, vec![vec![0], vec![0], vec![2]]);
+        assert_eq!(scanlines.consumed, raw.len());
+    }
+}
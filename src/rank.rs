@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+
+use regex::bytes::Regex;
+use regex_automata::{
+    dfa::{dense, Automaton},
+    nfa::thompson,
+    util::{start, syntax},
+    Anchored,
+};
+
+/// Letter frequencies of English text in percent.
+const LETTERS: [f64; 26] = [
+    8.2, 1.5, 2.8, 4.3, 12.7, 2.2, 2.0, 6.1, 7.0, 0.15, 0.8, 4.0, 2.4, 6.7, 7.5, 1.9, 0.1, 6.0,
+    6.3, 9.1, 2.8, 1.0, 2.4, 0.15, 2.0, 0.07,
+];
+
+const BIGRAMS: [&str; 30] = [
+    "th", "he", "in", "er", "an", "re", "on", "at", "en", "nd", "ti", "es", "or", "te", "of", "ed",
+    "is", "it", "al", "ar", "st", "to", "nt", "ng", "se", "ha", "as", "ou", "io", "le",
+];
+
+/// Number of partial plaintexts kept while joining the entries.
+const BEAM: usize = 256;
+
+/// Shows text as is and anything else with its non-ASCII bytes escaped.
+pub fn show(pt: &[u8]) -> String {
+    match std::str::from_utf8(pt) {
+        Ok(x) => x.to_owned(),
+        Err(_) => pt.escape_ascii().to_string(),
+    }
+}
+
+/// Log-likelihood of `pt` under a unigram model of English and flag formats, with bonuses
+/// for common English bigrams and a closed `{...}`.
+pub fn score(pt: &[u8]) -> f64 {
+    let unigrams = pt
+        .iter()
+        .map(|&c| match c {
+            b'a'..=b'z' => LETTERS[(c - b'a') as usize].ln(),
+            b'A'..=b'Z' => LETTERS[(c - b'A') as usize].ln() - 1.0,
+            b'0'..=b'9' => 2_f64.ln(),
+            b'_' | b'{' | b'}' => 1.5,
+            b' ' => 0.5,
+            b'-' | b'.' | b',' | b'!' | b'?' | b'@' | b'\'' => -1.0,
+            c if c.is_ascii_graphic() => -2.5,
+            _ => -5.0,
+        })
+        .sum::<f64>();
+
+    let lower = pt.to_ascii_lowercase();
+    let bigrams = lower
+        .windows(2)
+        .filter(|x| BIGRAMS.iter().any(|y| y.as_bytes() == *x))
+        .count();
+    let open = pt.iter().position(|&x| x == b'{');
+    let close = pt.iter().rposition(|&x| x == b'}');
+    let flag = match open.zip(close) {
+        Some((open, close)) if open < close => 5.0,
+        _ => 0.0,
+    };
+
+    unigrams + bigrams as f64 + flag
+}
+
+/// Joins the candidates of every entry in order with a beam search, most likely first.
+/// Plaintexts matching `pattern` are moved ahead of the rest. Joins that leave the pattern's
+/// DFA in the same state can only go on to match alike, so each state keeps its own beam
+/// and a join that can still match is never pruned for likelier ones that cannot.
+pub fn join(pieces: &[Vec<Vec<u8>>], pattern: Option<&Regex>) -> Vec<(f64, Vec<u8>)> {
+    let dfa = pattern.and_then(|x| {
+        dense::Builder::new()
+            .syntax(syntax::Config::new().utf8(false))
+            .thompson(thompson::Config::new().utf8(false))
+            .build(x.as_str())
+            .inspect_err(|e| tracing::warn!("Pattern {} only reorders the final beam ({}).", x, e))
+            .ok()
+    });
+    let start = dfa.as_ref().and_then(|x| {
+        x.start_state(&start::Config::new().anchored(Anchored::No))
+            .ok()
+    });
+
+    // Keyed by the DFA state, or `None` once the join has matched or without a DFA.
+    let mut beams = BTreeMap::from([((start, false), vec![(0.0, Vec::new())])]);
+    for pts in pieces.iter().filter(|x| !x.is_empty()) {
+        let mut next = BTreeMap::<_, Vec<_>>::new();
+        for (&(state, matched), beam) in &beams {
+            for pt in pts {
+                let key = match (&dfa, state) {
+                    (Some(dfa), Some(state)) => {
+                        let state = pt.iter().try_fold(state, |state, &x| {
+                            let state = dfa.next_state(state, x);
+                            match dfa.is_match_state(state) {
+                                true => Err(()),
+                                false => Ok(state),
+                            }
+                        });
+                        match state {
+                            Ok(state) => (Some(state), false),
+                            Err(()) => (None, true),
+                        }
+                    }
+                    _ => (None, matched),
+                };
+                next.entry(key)
+                    .or_default()
+                    .extend(beam.iter().map(|(_, prefix)| {
+                        let pt = [prefix.as_slice(), pt].concat();
+                        (score(&pt), pt)
+                    }));
+            }
+        }
+        for beam in next.values_mut() {
+            beam.sort_by(|a, b| b.0.total_cmp(&a.0));
+            beam.truncate(BEAM);
+        }
+        beams = next;
+    }
+
+    let mut ranked = beams
+        .into_iter()
+        .flat_map(|((state, matched), beam)| {
+            let matched = match (&dfa, state) {
+                (Some(dfa), Some(state)) => dfa.is_match_state(dfa.next_eoi_state(state)),
+                _ => matched,
+            };
+            beam.into_iter().map(move |x| (matched, x))
+        })
+        .collect::<Vec<_>>();
+    if let (Some(pattern), None) = (pattern, &dfa) {
+        ranked
+            .iter_mut()
+            .for_each(|(matched, (_, pt))| *matched = pattern.is_match(pt));
+    }
+    ranked.sort_by(|a, b| b.0.cmp(&a.0).then(b.1 .0.total_cmp(&a.1 .0)));
+    ranked.truncate(BEAM);
+    ranked.into_iter().map(|x| x.1).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::bytes::Regex;
+
+    use super::{join, show, BEAM};
+
+    #[test]
+    fn test_join() {
+        let pieces = [
+            vec![b"x#Q".to_vec(), b"fla".to_vec()],
+            vec![],
+            vec![b"\x01ab".to_vec(), b"g{a".to_vec()],
+            vec![b"bc}".to_vec()],
+        ];
+        let ranked = join(&pieces, None);
+        assert_eq!(ranked.len(), 4);
+        assert_eq!(ranked[0].1, b"flag{abc}");
+        assert!(ranked.windows(2).all(|x| x[0].0 >= x[1].0));
+
+        let pattern = Regex::new(r"^x#Q\x01").unwrap();
+        let ranked = join(&pieces, Some(&pattern));
+        assert_eq!(ranked[0].1, b"x#Q\x01abbc}");
+        assert_eq!(ranked[1].1, b"flag{abc}");
+
+        // 20 * 20 likely joins outscore the one matching the pattern, which must survive the cut.
+        let words = (b'a'..=b't')
+            .map(|x| vec![x, b'e', b' '])
+            .collect::<Vec<_>>();
+        let mut first = words.clone();
+        first.push(b"\x7f\x01".to_vec());
+        let mut second = words;
+        second.push(b"#~".to_vec());
+        let pieces = [first, second];
+        let ranked = join(&pieces, None);
+        assert_eq!(ranked.len(), BEAM);
+        assert!(ranked.iter().all(|x| x.1 != b"\x7f\x01#~"));
+        let pattern = Regex::new(r"\x01#").unwrap();
+        let ranked = join(&pieces, Some(&pattern));
+        assert_eq!(ranked[0].1, b"\x7f\x01#~");
+        assert!(ranked[1..].windows(2).all(|x| x[0].0 >= x[1].0));
+    }
+
+    #[test]
+    fn test_show() {
+        assert_eq!(show(b"flag"), "flag");
+        assert_eq!(show(b"\xff{"), "\\xff{");
+    }
+}
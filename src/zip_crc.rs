@@ -1,90 +1,270 @@
 use std::{
-    cmp::Ordering,
-    collections::{BTreeMap, HashMap},
-    fs::File,
-    sync::Arc,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{Cursor, Read, Seek},
 };
 
 use anyhow::Result;
 use async_trait::async_trait;
-use tokio::{sync::Mutex, task::JoinSet};
-use tracing::instrument;
+use regex::bytes::{Regex, RegexBuilder};
+use regex_syntax::{
+    hir::{Class, Hir, HirKind, Look},
+    ParserBuilder,
+};
+use tokio::sync::Mutex;
 use zip::ZipArchive;
 
-use crate::{error::Error, Command};
+use crate::{
+    archive::{self, Listed},
+    crc::{self, Context, SolutionMap},
+    mask, rank, zip_repair, Command,
+};
+
+/// Buckets keyed by size and the mask or template that narrows them, with their positions.
+type Plan = BTreeMap<(u64, Option<String>), (Vec<Vec<u8>>, SolutionMap)>;
 
-type SolutionMap = HashMap<u32, (String, Mutex<Vec<String>>)>;
+/// A regex the plaintext must match, narrowing every position when it has a fixed length.
+#[derive(Debug)]
+struct Template {
+    source: String,
+    regex: Regex,
+    positions: Option<Vec<Vec<u8>>>,
+}
 
 #[derive(Debug)]
 pub struct ZipCrc {
     file: String,
-    size: u64,
+    max_size: u64,
     alphabet: String,
+    charset: Option<String>,
+    masks: Vec<String>,
+    templates: Vec<String>,
+    pattern: Option<String>,
 }
 
 impl ZipCrc {
-    pub fn new(file: String, size: u64, alphabet: String) -> Self {
+    pub fn new(
+        file: String,
+        max_size: u64,
+        alphabet: String,
+        charset: Option<String>,
+        masks: Vec<String>,
+        templates: Vec<String>,
+        pattern: Option<String>,
+    ) -> Self {
         Self {
             file,
-            size,
+            max_size,
             alphabet,
+            charset,
+            masks,
+            templates,
+            pattern,
         }
     }
 
-    #[instrument(skip(ctx))]
-    async fn brute(first: char, ctx: Arc<Context>) {
-        let mut curr = first.to_string();
-        let mut stack = vec![ctx.alphabet.chars()];
-
-        while let Some(cs) = stack.last_mut() {
-            match curr.as_bytes().len().cmp(&ctx.size) {
-                Ordering::Greater => (),
-                Ordering::Equal => {
-                    let crc = crc32fast::hash(curr.as_bytes());
-                    if let Some((_, pts)) = ctx.crc2pts.get(&crc) {
-                        tracing::trace!(curr);
-                        pts.lock().await.push(curr.clone());
+    /// Splits `NAME=VALUE` specs, where the name `*` matches every entry.
+    fn parse_specs(specs: &[String]) -> Result<Vec<(String, String)>> {
+        specs
+            .iter()
+            .map(|x| {
+                x.split_once('=')
+                    .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                    .ok_or_else(|| anyhow::anyhow!("Expected NAME=VALUE, got {}.", x))
+            })
+            .collect()
+    }
+
+    fn spec_for<'a, T>(specs: &'a [(String, T)], name: &str) -> Option<&'a T> {
+        specs
+            .iter()
+            .find(|x| x.0 == name)
+            .or_else(|| specs.iter().find(|x| x.0 == "*"))
+            .map(|x| &x.1)
+    }
+
+    /// Splits every bucket by the mask or fixed-length template its entries follow, falling
+    /// back to the alphabet at every position when an entry has neither of its size. Entries
+    /// over `max_size` are only kept with one.
+    fn plan(
+        size2crcs: BTreeMap<u64, SolutionMap>,
+        masks: &[(String, String)],
+        templates: &[(String, Template)],
+        alphabet: &[u8],
+        max_size: u64,
+    ) -> Result<Plan> {
+        let mut plan = Plan::new();
+        for (size, crc2pts) in size2crcs {
+            for (crc, (name, pts)) in crc2pts {
+                let mut narrowed = None;
+                if let Some(mask) = Self::spec_for(masks, &name) {
+                    let positions = mask::parse(mask, Some(alphabet))?;
+                    match positions.len() as u64 == size {
+                        true => narrowed = Some((format!("mask {}", mask), positions)),
+                        false => tracing::warn!(
+                            "Mask {} has {} positions but {} has {} bytes, ignored.",
+                            mask,
+                            positions.len(),
+                            name,
+                            size
+                        ),
                     }
                 }
-                Ordering::Less => {
-                    if let Some(c) = cs.next() {
-                        curr.push(c);
-                        stack.push(ctx.alphabet.chars());
-                        continue;
+                if let Some(template) =
+                    Self::spec_for(templates, &name).filter(|_| narrowed.is_none())
+                {
+                    match &template.positions {
+                        Some(positions) if positions.len() as u64 == size => {
+                            narrowed = Some((format!("template {}", template.source), positions.clone()))
+                        }
+                        Some(positions) => tracing::warn!(
+                            "Template {} has {} positions but {} has {} bytes, only filtering with it.",
+                            template.source,
+                            positions.len(),
+                            name,
+                            size
+                        ),
+                        None => (),
                     }
                 }
+                if narrowed.is_none() && size > max_size {
+                    tracing::warn!(
+                        "{} has {} bytes and no mask or template, skipped.",
+                        name,
+                        size
+                    );
+                    continue;
+                }
+
+                let (label, positions) = match narrowed {
+                    Some((label, positions)) => (Some(label), positions),
+                    None => (None, vec![alphabet.to_vec(); size as usize]),
+                };
+                plan.entry((size, label))
+                    .or_insert_with(|| (positions, SolutionMap::new()))
+                    .1
+                    .insert(crc, (name, pts));
             }
+        }
+
+        Ok(plan)
+    }
 
-            curr.pop();
-            stack.pop();
+    /// Compiles a template into the bytes allowed at each position when it only matches one
+    /// length, with `.` standing for the alphabet. Alternatives of equal length merge position by
+    /// position, which widens the search but never loses a match.
+    fn compile_template(template: &str, alphabet: &[u8]) -> Option<Vec<Vec<u8>>> {
+        fn walk(hir: &Hir, alphabet: &[u8]) -> Option<Vec<Vec<u8>>> {
+            match hir.kind() {
+                HirKind::Empty => Some(Vec::new()),
+                HirKind::Look(Look::Start | Look::End) => Some(Vec::new()),
+                HirKind::Look(_) => None,
+                HirKind::Literal(literal) => Some(literal.0.iter().map(|&x| vec![x]).collect()),
+                HirKind::Class(Class::Bytes(class)) => {
+                    let bytes = class
+                        .ranges()
+                        .iter()
+                        .flat_map(|x| x.start()..=x.end())
+                        .collect::<Vec<_>>();
+                    match bytes.len() {
+                        255.. => Some(vec![alphabet.to_vec()]),
+                        _ => Some(vec![bytes]),
+                    }
+                }
+                HirKind::Class(Class::Unicode(class)) => {
+                    let bytes = class
+                        .ranges()
+                        .iter()
+                        .map(|x| Some(u8::try_from(x.start()).ok()?..=u8::try_from(x.end()).ok()?))
+                        .collect::<Option<Vec<_>>>()?;
+                    Some(vec![bytes
+                        .into_iter()
+                        .flatten()
+                        .filter(u8::is_ascii)
+                        .collect()])
+                }
+                HirKind::Repetition(repetition) if repetition.max == Some(repetition.min) => {
+                    let sub = walk(&repetition.sub, alphabet)?;
+                    Some(vec![sub; repetition.min as usize].concat())
+                }
+                HirKind::Repetition(_) => None,
+                HirKind::Capture(capture) => walk(&capture.sub, alphabet),
+                HirKind::Concat(hirs) => hirs
+                    .iter()
+                    .map(|x| walk(x, alphabet))
+                    .collect::<Option<Vec<_>>>()
+                    .map(|x| x.concat()),
+                HirKind::Alternation(hirs) => {
+                    let mut alternatives = hirs.iter().map(|x| walk(x, alphabet));
+                    let mut positions = alternatives.next()??;
+                    for alternative in alternatives {
+                        let alternative = alternative?;
+                        if alternative.len() != positions.len() {
+                            return None;
+                        }
+                        for (position, bytes) in positions.iter_mut().zip(alternative) {
+                            position.extend(bytes);
+                        }
+                    }
+                    Some(positions.into_iter().map(mask::dedup).collect())
+                }
+            }
         }
+
+        let hir = ParserBuilder::new()
+            .unicode(false)
+            .utf8(false)
+            .build()
+            .parse(template)
+            .ok()?;
+        walk(&hir, alphabet)
     }
 
-    fn init_buckets(mut zip: ZipArchive<File>, size: u64) -> Result<SolutionMap> {
-        let mut crc2pts = HashMap::with_capacity(zip.len());
+    /// Lists the files of an opened zip, skipping directories.
+    fn list_zip<R: Read + Seek>(mut zip: ZipArchive<R>) -> Result<Vec<Listed>> {
+        let mut files = Vec::with_capacity(zip.len());
         for i in 0..zip.len() {
             let entry = zip.by_index_raw(i)?;
-            if entry.size() == size {
-                let (name, crc) = (entry.name(), entry.crc32());
-                tracing::debug!("name={}, crc={:#x}", name, crc);
-
-                crc2pts
-                    .entry(crc)
-                    .or_insert_with(|| (name.to_owned(), Mutex::default()));
+            if !entry.is_dir() {
+                files.push(Listed {
+                    name: entry.name().to_owned(),
+                    size: entry.size(),
+                    crc: entry.crc32(),
+                });
             }
         }
 
-        Ok(crc2pts)
+        Ok(files)
     }
 
-    fn spawn_tasks(ctx: &Arc<Context>) -> JoinSet<()> {
-        let mut tasks = JoinSet::new();
-        for c in ctx.alphabet.chars() {
-            let ctx = ctx.clone();
-            tasks.spawn(Self::brute(c, ctx));
+    /// Groups every non-empty entry up to `max_size` bytes, or of any size when a mask or
+    /// template applies to it, by its size.
+    fn init_buckets(
+        files: Vec<Listed>,
+        max_size: u64,
+        masks: &[(String, String)],
+        templates: &[(String, Template)],
+    ) -> BTreeMap<u64, SolutionMap> {
+        let mut size2crcs = BTreeMap::<_, SolutionMap>::new();
+        for Listed { name, size, crc } in files {
+            let narrowed = Self::spec_for(masks, &name).is_some()
+                || Self::spec_for(templates, &name).is_some();
+            let limit = match narrowed {
+                true => u64::MAX,
+                false => max_size,
+            };
+            if (1..=limit).contains(&size) {
+                tracing::debug!("name={}, size={}, crc={:#x}", name, size, crc);
+
+                size2crcs
+                    .entry(size)
+                    .or_default()
+                    .entry(crc)
+                    .or_insert_with(|| (name, Mutex::default()));
+            }
         }
 
-        tasks
+        size2crcs
     }
 }
 
@@ -93,82 +273,227 @@ impl Command for ZipCrc {
     async fn execute(self: Box<Self>) -> Result<()> {
         let Self {
             file,
-            size,
+            max_size,
             alphabet,
+            charset,
+            masks,
+            templates,
+            pattern,
         } = *self;
-        let zip = ZipArchive::new(File::open(&file)?)?;
-        let crc2pts = Self::init_buckets(zip, size)?;
-        let ctx = Arc::new(Context::new(size, alphabet, crc2pts));
-
-        let mut tasks = Self::spawn_tasks(&ctx);
-        while let Some(result) = tasks.join_next().await {
-            result?;
-        }
-        tracing::debug!(?ctx.crc2pts);
-
-        let ctx = Arc::into_inner(ctx).ok_or(Error::ArcIntoInner)?;
-        let crc2pts_sorted = ctx
-            .crc2pts
+        let alphabet = match charset {
+            Some(charset) => mask::parse_charset(&charset)?,
+            None => mask::dedup(alphabet.into_bytes()),
+        };
+        let pattern = pattern.as_deref().map(Regex::new).transpose()?;
+        let masks = Self::parse_specs(&masks)?;
+        let templates = Self::parse_specs(&templates)?
             .into_iter()
-            .map(|(crc, (name, pts))| (name, (crc, pts.into_inner())))
-            .collect::<BTreeMap<_, _>>();
-        crc2pts_sorted.iter().for_each(|(name, (crc, pts))| {
-            tracing::info!("name={}, crc={:#x}, pts={:?}", name, crc, pts)
-        });
+            .map(|(name, source)| {
+                let regex = RegexBuilder::new(&format!("^(?:{})$", source))
+                    .unicode(false)
+                    .build()?;
+                let positions = Self::compile_template(&source, &alphabet);
+                if positions.is_none() {
+                    tracing::warn!(
+                        "Template {} has no fixed length, only filtering with it.",
+                        source
+                    );
+                }
+                Ok((
+                    name,
+                    Template {
+                        source,
+                        regex,
+                        positions,
+                    },
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-        let pt = crc2pts_sorted
-            .into_iter()
-            .flat_map(|(_, (_, pts))| pts)
-            .collect::<String>();
-        tracing::info!(pt);
+        // Only RAR and 7z are read whole, zips keep streaming from the file.
+        let mut head = Vec::with_capacity(8);
+        File::open(&file)?.take(8).read_to_end(&mut head)?;
+        let files = match archive::is_archive(&head) {
+            true => archive::list(&fs::read(&file)?)?.unwrap_or_default(),
+            false => match ZipArchive::new(File::open(&file)?) {
+                Ok(zip) => Self::list_zip(zip)?,
+                Err(e) => {
+                    tracing::warn!("Failed to open zip ({}), recovering from local headers.", e);
+                    let data = zip_repair::recover(&fs::read(&file)?)?;
+                    Self::list_zip(ZipArchive::new(Cursor::new(data))?)?
+                }
+            },
+        };
+        let size2crcs = Self::init_buckets(files, max_size, &masks, &templates);
 
-        Ok(())
-    }
-}
+        let mut crc2pts_sorted = BTreeMap::new();
+        let plan = Self::plan(size2crcs, &masks, &templates, &alphabet, max_size)?;
+        for ((size, label), (positions, crc2pts)) in plan {
+            match label {
+                Some(label) => tracing::info!(
+                    "Solving {} CRCs of size {} with {}.",
+                    crc2pts.len(),
+                    size,
+                    label
+                ),
+                None => tracing::info!("Solving {} CRCs of size {}.", crc2pts.len(), size),
+            }
+            let ctx = crc::solve(Context::new(positions, crc2pts, crc::CRC32)).await?;
+            tracing::debug!(?ctx.crc2pts);
 
-#[derive(Debug)]
-struct Context {
-    pub size: usize,
-    pub alphabet: String,
-    pub crc2pts: HashMap<u32, (String, Mutex<Vec<String>>)>,
-}
+            crc2pts_sorted.extend(
+                ctx.crc2pts
+                    .into_iter()
+                    .map(|(crc, (name, pts))| (name, (crc, pts.into_inner()))),
+            );
+        }
 
-impl Context {
-    fn new(
-        size: u64,
-        alphabet: String,
-        crc2pts: HashMap<u32, (String, Mutex<Vec<String>>)>,
-    ) -> Self {
-        Self {
-            size: size.try_into().expect("Failed to convert u64 to usize."),
-            alphabet,
-            crc2pts,
+        let mut pieces = Vec::with_capacity(crc2pts_sorted.len());
+        for (name, (crc, mut pts)) in crc2pts_sorted {
+            if let Some(template) = Self::spec_for(&templates, &name) {
+                pts.retain(|x| template.regex.is_match(x));
+            }
+            pts.sort_by(|a, b| rank::score(b).total_cmp(&rank::score(a)));
+            let shown = pts.iter().map(|x| rank::show(x)).collect::<Vec<_>>();
+            tracing::info!(
+                "name={}, crc={:#x}, count={}, pts={:?}",
+                name,
+                crc,
+                pts.len(),
+                &shown[..shown.len().min(5)]
+            );
+            tracing::debug!("name={}, pts={:?}", name, shown);
+            pieces.push(pts);
+        }
+
+        let ranked = rank::join(&pieces, pattern.as_ref());
+        for (i, (score, pt)) in ranked.iter().enumerate() {
+            let pt = rank::show(pt);
+            match i {
+                0..3 => tracing::info!("rank={}, score={:.2}, pt={:?}", i, score, pt),
+                _ => tracing::debug!("rank={}, score={:.2}, pt={:?}", i, score, pt),
+            }
         }
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{
+        collections::BTreeMap,
+        io::{Cursor, Write},
+    };
 
+    use regex::bytes::Regex;
     use tokio::sync::Mutex;
+    use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+    use super::{Template, ZipCrc};
+    use crate::{
+        crc::{self, Context, SolutionMap},
+        mask,
+    };
+
+    #[test]
+    fn test_init_buckets() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, pt) in [
+            ("2", "ag{"),
+            ("1", "fl"),
+            ("3", "crc}"),
+            ("4", "toolong"),
+            ("5", ""),
+        ] {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(pt.as_bytes()).unwrap();
+        }
+        zip.add_directory("dir", FileOptions::default()).unwrap();
+        let zip = ZipArchive::new(zip.finish().unwrap()).unwrap();
+
+        let files = ZipCrc::list_zip(zip).unwrap();
+        let sizes = |masks: &[(&str, &str)]| {
+            let masks = masks
+                .iter()
+                .map(|(name, mask)| (name.to_string(), mask.to_string()))
+                .collect::<Vec<_>>();
+            ZipCrc::init_buckets(files.clone(), 3, &masks, &[])
+                .iter()
+                .map(|(size, crc2pts)| {
+                    let mut names = crc2pts.values().map(|x| x.0.clone()).collect::<Vec<_>>();
+                    names.sort();
+                    (*size, names)
+                })
+                .collect::<Vec<_>>()
+        };
+        let small = [(2, vec!["1".to_owned()]), (3, vec!["2".to_owned()])];
+        assert_eq!(sizes(&[]), small);
+
+        let long = (7, vec!["4".to_owned()]);
+        assert_eq!(
+            sizes(&[("4", "t?????g")]),
+            [small[0].clone(), small[1].clone(), long.clone()]
+        );
 
-    use super::{Context, SolutionMap, ZipCrc};
+        // The `*` mask applies to every entry, so none is limited by size.
+        let all = [("*", "t?????g")];
+        let middle = (4, vec!["3".to_owned()]);
+        assert_eq!(
+            sizes(&all),
+            [small[0].clone(), small[1].clone(), middle, long]
+        );
+    }
 
     #[tokio::test]
-    async fn test_brute() {
-        let flag = "flag".to_owned();
-        let crc = crc32fast::hash(flag.as_bytes());
-        let mut crc2pts = SolutionMap::new();
-        crc2pts.insert(
-            crc,
-            ("demo.txt".to_owned(), Mutex::<Vec<String>>::default()),
+    async fn test_template() {
+        let alphabet = mask::preset("printable").unwrap();
+        let compile = |x| ZipCrc::compile_template(x, &alphabet);
+        assert_eq!(
+            compile("fl[aA]g\\d{2}").unwrap(),
+            [
+                vec![b'f'],
+                vec![b'l'],
+                b"Aa".to_vec(),
+                vec![b'g'],
+                b"0123456789".to_vec(),
+                b"0123456789".to_vec()
+            ]
         );
-        let alphabet = ('a'..'z').collect();
-        let ctx = Arc::new(Context::new(4, alphabet, crc2pts));
-        ZipCrc::brute('f', ctx.clone()).await;
+        assert_eq!(
+            compile("(ab|cd).").unwrap(),
+            [b"ac".to_vec(), b"bd".to_vec(), alphabet.clone()]
+        );
+        for template in ["a+", "a{1,2}", "ab|c", "\\b"] {
+            assert!(compile(template).is_none(), "{}", template);
+        }
+
+        // The 10-byte entry is over the size limit and only the template makes it solvable.
+        let pt = b"flag{7x5}_";
+        let mut crc2pts = SolutionMap::new();
+        let crc = crc32fast::hash(pt);
+        crc2pts.insert(crc, ("1".to_owned(), Mutex::default()));
+        let source = "flag\\{\\d[a-z]\\d\\}_".to_owned();
+        let template = Template {
+            regex: Regex::new(&source).unwrap(),
+            positions: compile(&source),
+            source,
+        };
+        let plan = ZipCrc::plan(
+            BTreeMap::from([(10, crc2pts)]),
+            &[],
+            &[("*".to_owned(), template)],
+            &alphabet,
+            6,
+        )
+        .unwrap();
+        let ((_, label), (positions, crc2pts)) = plan.into_iter().next().unwrap();
+        assert!(label.unwrap().starts_with("template"));
 
-        let Context { mut crc2pts, .. } = Arc::into_inner(ctx).unwrap();
-        assert_eq!(crc2pts.remove(&crc).unwrap().1.into_inner(), vec![flag]);
+        let ctx = crc::solve(Context::new(positions, crc2pts, crc::CRC32))
+            .await
+            .unwrap();
+        let pts = ctx.crc2pts[&crc].1.lock().await.clone();
+        assert_eq!(pts, [pt.to_vec()]);
     }
 }
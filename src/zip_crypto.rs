@@ -0,0 +1,657 @@
+use std::{
+    io::Cursor,
+    num::NonZeroUsize,
+    ops::Range,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::{crc, error::Error};
+
+const MULT: u32 = 0x08088405;
+const MULTINV: u32 = 0xd94fa8cd;
+
+const MASK_0_16: u32 = 0x0000ffff;
+const MASK_0_24: u32 = 0x00ffffff;
+const MASK_0_26: u32 = 0x03ffffff;
+const MASK_2_32: u32 = 0xfffffffc;
+const MASK_8_32: u32 = 0xffffff00;
+const MASK_10_32: u32 = 0xfffffc00;
+const MASK_24_32: u32 = 0xff000000;
+const MASK_26_32: u32 = 0xfc000000;
+
+/// Largest difference between a value and its known most significant bits plus a carried byte.
+const MAXDIFF_0_24: u32 = MASK_0_24 + 0xff;
+const MAXDIFF_0_26: u32 = MASK_0_26 + 0xff;
+
+/// Contiguous keystream bytes the attack solves for, the rest only filters candidates.
+const CONTIGUOUS_SIZE: usize = 8;
+
+/// Minimum number of contiguous known plaintext bytes.
+pub const ATTACK_SIZE: usize = 12;
+
+pub const HEADER_SIZE: usize = 12;
+
+static TABLES: Lazy<Tables> = Lazy::new(Tables::new);
+
+/// Lookup tables inverting the keystream byte and the multiplication by [`MULT`].
+#[derive(Debug)]
+struct Tables {
+    /// Z[2,16) values giving a keystream byte, bucketed by their bits [10,16).
+    keystream: Vec<Vec<Vec<u32>>>,
+    /// Bytes x whose msb(x * MULTINV) is within 1 of the index.
+    fiber2: Vec<Vec<u8>>,
+    /// Bytes x whose msb(x * MULTINV) is within 2 of the index.
+    fiber3: Vec<Vec<u8>>,
+}
+
+impl Tables {
+    fn new() -> Self {
+        let mut keystream = vec![vec![Vec::new(); 64]; 256];
+        for z in (0..1 << 16).step_by(4) {
+            let k = Keys::keystream_of(z);
+            keystream[k as usize][(z >> 10) as usize].push(z);
+        }
+
+        let mut fiber2 = vec![Vec::new(); 256];
+        let mut fiber3 = vec![Vec::new(); 256];
+        for x in 0..=u8::MAX {
+            let msb = msb((x as u32).wrapping_mul(MULTINV));
+            fiber2[msb as usize].push(x);
+            fiber2[msb.wrapping_add(1) as usize].push(x);
+            fiber3[msb.wrapping_sub(1) as usize].push(x);
+            fiber3[msb as usize].push(x);
+            fiber3[msb.wrapping_add(1) as usize].push(x);
+        }
+
+        Self {
+            keystream,
+            fiber2,
+            fiber3,
+        }
+    }
+
+    /// Z[2,16) values producing keystream byte `k` that agree with the bits [10,16) of `z`.
+    fn z_2_16(&self, k: u8, z: u32) -> &[u32] {
+        &self.keystream[k as usize][((z & MASK_0_16) >> 10) as usize]
+    }
+}
+
+fn msb(x: u32) -> u8 {
+    (x >> 24) as u8
+}
+
+/// Y{i}[24,32) from Z{i} and Z{i-1}, by the CRC32 step Z{i} = crc32(Z{i-1}, msb(Y{i})).
+fn y_24_32(z: u32, zm1: u32) -> u32 {
+    (crc::update_backward(z, 0) ^ zm1) << 24
+}
+
+/// Z{i-1}[10,32) from Z{i}[2,32), which does not depend on the unknown msb(Y{i}).
+fn zm1_10_32(z: u32) -> u32 {
+    crc::update_backward(z, 0) & MASK_10_32
+}
+
+/// The three internal ZipCrypto keys.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Keys {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+impl Keys {
+    pub fn new(password: &[u8]) -> Self {
+        let mut keys = Self {
+            x: 0x12345678,
+            y: 0x23456789,
+            z: 0x34567890,
+        };
+        password.iter().for_each(|&x| keys.update(x));
+
+        keys
+    }
+
+    fn keystream_of(z: u32) -> u8 {
+        let z = z | 2;
+        (z.wrapping_mul(z ^ 1) >> 8) as u8
+    }
+
+    pub fn keystream(&self) -> u8 {
+        Self::keystream_of(self.z)
+    }
+
+    pub fn update(&mut self, p: u8) {
+        self.x = crc::update(self.x, p);
+        self.y = self
+            .y
+            .wrapping_add(self.x & 0xff)
+            .wrapping_mul(MULT)
+            .wrapping_add(1);
+        self.z = crc::update(self.z, msb(self.y));
+    }
+
+    /// Steps back over ciphertext byte `c`, leaving the keys that encrypted it.
+    pub fn update_backward(&mut self, c: u8) {
+        self.z = crc::update_backward(self.z, msb(self.y));
+        self.y = self
+            .y
+            .wrapping_sub(1)
+            .wrapping_mul(MULTINV)
+            .wrapping_sub(self.x & 0xff);
+        self.x = crc::update_backward(self.x, c ^ self.keystream());
+    }
+
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        for c in data {
+            *c ^= self.keystream();
+            self.update(*c);
+        }
+    }
+
+    pub fn encrypt(&mut self, data: &mut [u8]) {
+        for p in data {
+            let k = self.keystream();
+            self.update(*p);
+            *p ^= k;
+        }
+    }
+}
+
+/// Where an entry's data sits in the archive, with the header fields the zip crate hides.
+#[derive(Debug)]
+pub struct Entry {
+    pub name: String,
+    pub enclosed: Option<PathBuf>,
+    pub encrypted: bool,
+    /// Encrypted with WinZip AES rather than ZipCrypto.
+    pub aes: bool,
+    pub compression: CompressionMethod,
+    pub crc: u32,
+    /// Last byte of the decrypted encryption header.
+    pub check: u8,
+    /// Offsets of the local and central file headers.
+    pub local: usize,
+    pub central: usize,
+    pub data: Range<usize>,
+}
+
+pub fn entries(data: &[u8]) -> Result<Vec<Entry>> {
+    let mut zip = ZipArchive::new(Cursor::new(data))?;
+    let mut entries = Vec::with_capacity(zip.len());
+    for i in 0..zip.len() {
+        let file = zip.by_index_raw(i)?;
+        let header = file.header_start() as usize;
+        let u16_at = |i: usize| u16::from_le_bytes([data[header + i], data[header + i + 1]]);
+        let (flags, method, time) = (u16_at(6), u16_at(8), u16_at(10));
+
+        // With a data descriptor the CRC is not known up front, so the time is checked instead.
+        let check = match flags & 0x08 {
+            0 => (file.crc32() >> 24) as u8,
+            _ => (time >> 8) as u8,
+        };
+        // A truncated archive or a lying central directory must not send readers out of bounds.
+        let start = file.data_start() as usize;
+        let end = start
+            .checked_add(file.compressed_size() as usize)
+            .filter(|&x| x <= data.len())
+            .ok_or_else(|| Error::ZipData(file.name().to_owned()))?;
+        entries.push(Entry {
+            name: file.name().to_owned(),
+            enclosed: file.enclosed_name().map(PathBuf::from),
+            encrypted: flags & 0x01 != 0,
+            aes: method == 99,
+            compression: file.compression(),
+            crc: file.crc32(),
+            check,
+            local: header,
+            central: file.central_header_start() as usize,
+            data: start..end,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Known plaintext of an entry, `offset` is where it starts in the ciphertext including the
+/// encryption header.
+#[derive(Debug)]
+pub struct Known<'a> {
+    pub ciphertext: &'a [u8],
+    pub plaintext: &'a [u8],
+    pub offset: usize,
+}
+
+impl Known<'_> {
+    /// Recovers the keys right after the password was processed, which decrypt every entry
+    /// sharing it. This is Biham and Kocher's attack as refined by bkcrack.
+    pub fn attack(&self) -> Result<Option<Keys>, Error> {
+        let available = self.ciphertext.len().saturating_sub(self.offset);
+        let plaintext = &self.plaintext[..self.plaintext.len().min(available)];
+        if plaintext.len() < ATTACK_SIZE {
+            return Err(Error::ZipPlaintext(ATTACK_SIZE, plaintext.len()));
+        }
+
+        let keystream = plaintext
+            .iter()
+            .zip(&self.ciphertext[self.offset..])
+            .map(|(p, c)| p ^ c)
+            .collect::<Vec<_>>();
+        let (index, candidates) = Self::reduce(&keystream);
+        tracing::info!(
+            "Reduced to {} Z candidates at keystream index {}.",
+            candidates.len(),
+            index
+        );
+
+        let known = Known { plaintext, ..*self };
+        Ok(known.search(&keystream, index, &candidates))
+    }
+
+    /// Carries out every Z candidate at keystream `index` on a thread per core until one of them
+    /// gives the keys.
+    fn search(&self, keystream: &[u8], index: usize, candidates: &[u32]) -> Option<Keys> {
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let (next, done) = (AtomicUsize::new(0), AtomicBool::new(false));
+        thread::scope(|s| {
+            let handles = (0..threads)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut attack = Attack::new(self, keystream, index + 1 - CONTIGUOUS_SIZE);
+                        while !done.load(Ordering::Relaxed) {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(&z) = candidates.get(i) else {
+                                break;
+                            };
+                            if i % 1024 == 0 {
+                                tracing::debug!("Trying candidate {}/{}.", i, candidates.len());
+                            }
+
+                            if let Some(keys) = attack.carry_out(z) {
+                                done.store(true, Ordering::Relaxed);
+                                return Some(keys);
+                            }
+                        }
+                        None
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .filter_map(|x| x.join().expect("Failed to join attack thread."))
+                .next()
+        })
+    }
+
+    /// Narrows the 2^22 possible Z[10,32) values of the last keystream byte down by walking
+    /// backwards, then returns the full Z[2,32) candidates where they were fewest.
+    fn reduce(keystream: &[u8]) -> (usize, Vec<u32>) {
+        const TRACK_SIZE: usize = 1 << 16;
+
+        let tables = &*TABLES;
+        let last = keystream.len() - 1;
+        let mut zi = (0..1 << 22)
+            .map(|x| x << 10)
+            .filter(|&z| !tables.z_2_16(keystream[last], z).is_empty())
+            .collect::<Vec<u32>>();
+
+        let mut best: Option<(usize, usize, Vec<u32>)> = None;
+        let mut seen = vec![false; 1 << 22];
+        for i in (CONTIGUOUS_SIZE..=last).rev() {
+            let mut zim1 = Vec::new();
+            let mut count = 0;
+            seen.fill(false);
+            for &z_10_32 in &zi {
+                for &z_2_16 in tables.z_2_16(keystream[i], z_10_32) {
+                    let zm1 = zm1_10_32(z_10_32 | z_2_16);
+                    let zm1_2_16 = tables.z_2_16(keystream[i - 1], zm1);
+                    if !seen[(zm1 >> 10) as usize] && !zm1_2_16.is_empty() {
+                        seen[(zm1 >> 10) as usize] = true;
+                        zim1.push(zm1);
+                        count += zm1_2_16.len();
+                    }
+                }
+            }
+
+            if count <= best.as_ref().map_or(TRACK_SIZE, |x| x.1) {
+                best = Some((i - 1, count, zim1.clone()));
+            }
+            zi = zim1;
+        }
+
+        let (index, zi) = best.map_or((CONTIGUOUS_SIZE - 1, zi), |x| (x.0, x.2));
+        let candidates = zi
+            .iter()
+            .flat_map(|&z| {
+                tables
+                    .z_2_16(keystream[index], z)
+                    .iter()
+                    .map(move |x| z | x)
+            })
+            .collect();
+
+        (index, candidates)
+    }
+}
+
+/// One worker of the attack, completing the Z, Y and X lists of 8 contiguous keystream bytes
+/// from a Z candidate.
+#[derive(Debug)]
+struct Attack<'a> {
+    known: &'a Known<'a>,
+    keystream: &'a [u8],
+    index: usize,
+    xlist: [u32; 8],
+    ylist: [u32; 8],
+    zlist: [u32; 8],
+}
+
+impl<'a> Attack<'a> {
+    fn new(known: &'a Known, keystream: &'a [u8], index: usize) -> Self {
+        Self {
+            known,
+            keystream,
+            index,
+            xlist: [0; 8],
+            ylist: [0; 8],
+            zlist: [0; 8],
+        }
+    }
+
+    fn carry_out(&mut self, z7_2_32: u32) -> Option<Keys> {
+        self.zlist[7] = z7_2_32;
+        self.explore_z(7)
+    }
+
+    fn explore_z(&mut self, i: usize) -> Option<Keys> {
+        let tables = &*TABLES;
+        if i != 0 {
+            let zim1_10_32 = zm1_10_32(self.zlist[i]);
+            let k = self.keystream[self.index + i - 1];
+            for &zim1_2_16 in tables.z_2_16(k, zim1_10_32) {
+                self.zlist[i - 1] = zim1_10_32 | zim1_2_16;
+
+                // The CRC32 step fixes Z{i}[0,2) once Z{i-1} is known.
+                self.zlist[i] &= MASK_2_32;
+                self.zlist[i] |= (crc::update_backward(self.zlist[i], 0) ^ self.zlist[i - 1]) >> 8;
+                if i < 7 {
+                    self.ylist[i + 1] = y_24_32(self.zlist[i + 1], self.zlist[i]);
+                }
+
+                if let Some(keys) = self.explore_z(i - 1) {
+                    return Some(keys);
+                }
+            }
+
+            return None;
+        }
+
+        // Guess Y7[8,24), keeping prod == (Y7[8,32) - 1) * MULTINV.
+        let mut prod =
+            ((msb(self.ylist[7]) as u32).wrapping_mul(MULTINV) << 24).wrapping_sub(MULTINV);
+        for y7_8_24 in (0..1 << 24).step_by(1 << 8) {
+            let fiber = msb(self.ylist[6]).wrapping_sub(msb(prod));
+            for &y7_0_8 in &tables.fiber3[fiber as usize] {
+                let diff = prod
+                    .wrapping_add((y7_0_8 as u32).wrapping_mul(MULTINV))
+                    .wrapping_sub(self.ylist[6] & MASK_24_32);
+                if diff <= MAXDIFF_0_24 {
+                    self.ylist[7] = y7_0_8 as u32 | y7_8_24 | (self.ylist[7] & MASK_24_32);
+                    if let Some(keys) = self.explore_y(7) {
+                        return Some(keys);
+                    }
+                }
+            }
+            prod = prod.wrapping_add(MULTINV << 8);
+        }
+
+        None
+    }
+
+    fn explore_y(&mut self, i: usize) -> Option<Keys> {
+        if i == 3 {
+            return self.test_x();
+        }
+
+        let fy = self.ylist[i].wrapping_sub(1).wrapping_mul(MULTINV);
+        let ffy = fy.wrapping_sub(1).wrapping_mul(MULTINV);
+        let yim2 = self.ylist[i - 2] & MASK_24_32;
+        for &xi_0_8 in &TABLES.fiber2[msb(ffy.wrapping_sub(yim2)) as usize] {
+            let yim1 = fy.wrapping_sub(xi_0_8 as u32);
+            let diff = ffy
+                .wrapping_sub((xi_0_8 as u32).wrapping_mul(MULTINV))
+                .wrapping_sub(yim2);
+            if diff <= MAXDIFF_0_24 && msb(yim1) == msb(self.ylist[i - 1]) {
+                self.ylist[i - 1] = yim1;
+                self.xlist[i] = xi_0_8 as u32;
+                if let Some(keys) = self.explore_y(i - 1) {
+                    return Some(keys);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn test_x(&mut self) -> Option<Keys> {
+        let (plaintext, ciphertext) = (self.known.plaintext, self.known.ciphertext);
+        let (index, offset) = (self.index, self.known.offset);
+
+        // X7 only depends on the plaintext and the LSBs of X4..X7.
+        for i in 5..=7 {
+            self.xlist[i] = (crc::update(self.xlist[i - 1], plaintext[index + i - 1]) & MASK_8_32)
+                | (self.xlist[i] & 0xff);
+        }
+        let x3 = (3..=6).rev().fold(self.xlist[7], |x, i| {
+            crc::update_backward(x, plaintext[index + i])
+        });
+
+        let y1_26_32 = y_24_32(self.zlist[1], self.zlist[0]) & MASK_26_32;
+        let diff = self.ylist[3]
+            .wrapping_sub(1)
+            .wrapping_mul(MULTINV)
+            .wrapping_sub(x3 & 0xff)
+            .wrapping_sub(1)
+            .wrapping_mul(MULTINV)
+            .wrapping_sub(y1_26_32);
+        if diff > MAXDIFF_0_26 {
+            return None;
+        }
+
+        let mut forward = Keys {
+            x: self.xlist[7],
+            y: self.ylist[7],
+            z: self.zlist[7],
+        };
+        forward.update(plaintext[index + 7]);
+        for i in index + 8..plaintext.len() {
+            if ciphertext[offset + i] ^ forward.keystream() != plaintext[i] {
+                return None;
+            }
+            forward.update(plaintext[i]);
+        }
+
+        let mut backward = Keys {
+            x: x3,
+            y: self.ylist[3],
+            z: self.zlist[3],
+        };
+        for i in (0..index + 3).rev() {
+            backward.update_backward(ciphertext[offset + i]);
+            if ciphertext[offset + i] ^ backward.keystream() != plaintext[i] {
+                return None;
+            }
+        }
+        ciphertext[..offset]
+            .iter()
+            .rev()
+            .for_each(|&c| backward.update_backward(c));
+
+        Some(backward)
+    }
+}
+
+/// Stores each `(name, plain, password, deflated)` behind a ZipCrypto header, then patches
+/// the headers to match, for the tests of the commands built on this module.
+#[cfg(test)]
+pub(crate) fn encrypted(files: &[(&str, &[u8], &[u8], bool)]) -> Vec<u8> {
+    use std::io::Write;
+
+    use flate2::{write::DeflateEncoder, Compression};
+    use zip::{write::FileOptions, ZipWriter};
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for &(name, plain, _, deflated) in files {
+        let mut body = vec![0; HEADER_SIZE];
+        body[HEADER_SIZE - 1] = (crc32fast::hash(plain) >> 24) as u8;
+        match deflated {
+            true => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(plain).unwrap();
+                body.extend(encoder.finish().unwrap());
+            }
+            false => body.extend_from_slice(plain),
+        }
+        zip.start_file(name, options).unwrap();
+        zip.write_all(&body).unwrap();
+    }
+    let mut data = zip.finish().unwrap().into_inner();
+
+    for (entry, &(_, plain, password, deflated)) in entries(&data).unwrap().iter().zip(files) {
+        Keys::new(password).encrypt(&mut data[entry.data.clone()]);
+        let crc = crc32fast::hash(plain);
+        for (header, offset) in [(entry.local, 6), (entry.central, 8)] {
+            data[header + offset] |= 0x01;
+            if deflated {
+                data[header + offset + 2] = 8;
+            }
+            data[header + offset + 8..header + offset + 12].copy_from_slice(&crc.to_le_bytes());
+            data[header + offset + 16..header + offset + 20]
+                .copy_from_slice(&(plain.len() as u32).to_le_bytes());
+        }
+    }
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+    use super::{entries, Attack, Keys, Known, CONTIGUOUS_SIZE, HEADER_SIZE, MASK_2_32};
+    use crate::error::Error;
+
+    fn encrypt(password: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut data = (0..HEADER_SIZE as u8)
+            .map(|x| x.wrapping_mul(37))
+            .collect::<Vec<_>>();
+        data.extend_from_slice(plaintext);
+        Keys::new(password).encrypt(&mut data);
+        data
+    }
+
+    #[test]
+    fn test_keys() {
+        let keys = Keys::new(b"");
+        assert_eq!(
+            (keys.x, keys.y, keys.z),
+            (0x12345678, 0x23456789, 0x34567890)
+        );
+
+        let mut data = encrypt(b"secret", b"hello, zip");
+        Keys::new(b"secret").decrypt(&mut data);
+        assert_eq!(&data[HEADER_SIZE..], b"hello, zip");
+
+        let mut keys = Keys::new(b"secret");
+        let before = keys;
+        keys.update(b'a');
+        let c = b'a' ^ before.keystream();
+        keys.update_backward(c);
+        assert_eq!(keys, before);
+    }
+
+    #[test]
+    fn test_attack() {
+        let plaintext = (0..200_u32)
+            .map(|x| (x * x + 7 * x) as u8)
+            .collect::<Vec<_>>();
+        let ciphertext = encrypt(b"s3cr3t!", &plaintext);
+        let known = Known {
+            ciphertext: &ciphertext,
+            plaintext: &plaintext[20..40],
+            offset: HEADER_SIZE + 20,
+        };
+        let keystream = known
+            .plaintext
+            .iter()
+            .zip(&ciphertext[known.offset..])
+            .map(|(p, c)| p ^ c)
+            .collect::<Vec<_>>();
+
+        // Replay the encryption to learn the real Z key at every keystream byte.
+        let mut keys = Keys::new(b"s3cr3t!");
+        let mut header = ciphertext[..HEADER_SIZE].to_vec();
+        keys.decrypt(&mut header);
+        let zs = plaintext
+            .iter()
+            .map(|&p| {
+                let z = keys.z;
+                keys.update(p);
+                z
+            })
+            .skip(20)
+            .collect::<Vec<_>>();
+
+        let (index, candidates) = Known::reduce(&keystream);
+        let z = zs[index] & MASK_2_32;
+        assert!(candidates.contains(&z));
+
+        let mut attack = Attack::new(&known, &keystream, index + 1 - CONTIGUOUS_SIZE);
+        assert_eq!(attack.carry_out(z), Some(Keys::new(b"s3cr3t!")));
+
+        // The threaded search skips wrong candidates and stops at the right one.
+        let mut wrong = candidates
+            .iter()
+            .filter(|&&x| x != z)
+            .take(16)
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(known.search(&keystream, index, &wrong), None);
+        wrong.extend([z, z ^ 4]);
+        assert_eq!(
+            known.search(&keystream, index, &wrong),
+            Some(Keys::new(b"s3cr3t!"))
+        );
+
+        let short = Known {
+            plaintext: &plaintext[..11],
+            ..known
+        };
+        assert!(short.attack().is_err());
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("flag.txt", options).unwrap();
+        zip.write_all(b"flag{entries}").unwrap();
+        let mut data = zip.finish().unwrap().into_inner();
+
+        let entry = entries(&data).unwrap().remove(0);
+        assert_eq!(&data[entry.data.clone()], b"flag{entries}");
+
+        // A central directory claiming more data than the archive holds.
+        data[entry.central + 20..entry.central + 24].copy_from_slice(&0x10000_u32.to_le_bytes());
+        let error = entries(&data).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(Error::ZipData(x)) if x == "flag.txt"));
+    }
+}
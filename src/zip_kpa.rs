@@ -0,0 +1,285 @@
+use std::{io::Read, path::PathBuf};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use flate2::read::DeflateDecoder;
+use tokio::{fs, task};
+use zip::CompressionMethod;
+
+use crate::{
+    zip_crypto::{self, Entry, Keys, Known, HEADER_SIZE},
+    Command,
+};
+
+#[derive(Debug)]
+pub struct ZipKpa {
+    file: String,
+    entry: Option<String>,
+    plain: Option<String>,
+    plain_zip: Option<String>,
+    offset: usize,
+    password: Option<String>,
+}
+
+impl ZipKpa {
+    pub fn new(
+        file: String,
+        entry: Option<String>,
+        plain: Option<String>,
+        plain_zip: Option<String>,
+        offset: usize,
+        password: Option<String>,
+    ) -> Self {
+        Self {
+            file,
+            entry,
+            plain,
+            plain_zip,
+            offset,
+            password,
+        }
+    }
+
+    /// Decrypts an entry and undoes its compression, returning `None` for a wrong check byte.
+    fn decrypt(data: &[u8], entry: &Entry, keys: Keys) -> Result<Option<Vec<u8>>> {
+        let mut raw = data[entry.data.clone()].to_vec();
+        let mut keys = keys;
+        keys.decrypt(&mut raw);
+        if raw.get(HEADER_SIZE - 1) != Some(&entry.check) {
+            return Ok(None);
+        }
+
+        let body = &raw[HEADER_SIZE..];
+        let plain = match entry.compression {
+            CompressionMethod::Stored => body.to_vec(),
+            CompressionMethod::Deflated => {
+                let mut plain = Vec::new();
+                DeflateDecoder::new(body).read_to_end(&mut plain)?;
+                plain
+            }
+            method => {
+                tracing::warn!("Kept {} compressed with {:?}.", entry.name, method);
+                body.to_vec()
+            }
+        };
+
+        Ok(Some(plain))
+    }
+
+    /// Rewrites every entry the keys decrypt in place under a new password, nothing else
+    /// changes size. Entries under another password keep their bytes.
+    fn repack(data: &[u8], entries: &[Entry], keys: Keys, password: &str) -> Vec<u8> {
+        let mut repacked = data.to_vec();
+        for entry in entries.iter().filter(|x| x.encrypted && !x.aes) {
+            let mut raw = data[entry.data.clone()].to_vec();
+            let mut keys = keys;
+            keys.decrypt(&mut raw);
+            if raw.get(HEADER_SIZE - 1) != Some(&entry.check) {
+                tracing::warn!("Check byte of {} does not match, kept as is.", entry.name);
+                continue;
+            }
+
+            Keys::new(password.as_bytes()).encrypt(&mut raw);
+            repacked[entry.data.clone()].copy_from_slice(&raw);
+        }
+
+        repacked
+    }
+}
+
+#[async_trait]
+impl Command for ZipKpa {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self {
+            file,
+            entry,
+            plain,
+            plain_zip,
+            offset,
+            password,
+        } = *self;
+
+        let data = fs::read(&file).await?;
+        let entries = zip_crypto::entries(&data)?;
+        let plain_entries = match &plain_zip {
+            Some(plain_zip) => {
+                let data = fs::read(plain_zip).await?;
+                let entries = zip_crypto::entries(&data)?;
+                Some((data, entries))
+            }
+            None => None,
+        };
+
+        let target = entries
+            .iter()
+            .filter(|x| x.encrypted && !x.aes)
+            .find(|x| match (&entry, &plain_entries) {
+                (Some(name), _) => &x.name == name,
+                (None, Some((_, plain))) => plain.iter().any(|y| y.name == x.name),
+                (None, None) => true,
+            })
+            .ok_or_else(|| anyhow::anyhow!("No matching encrypted entry found."))?;
+
+        let plaintext = match (&plain, &plain_entries) {
+            (Some(plain), _) => {
+                // Known plaintext is compared with the entry's data as stored, after compression.
+                if target.compression != CompressionMethod::Stored {
+                    tracing::warn!(
+                        "Entry is {:?}, --plain must hold its compressed bytes, not the file.",
+                        target.compression
+                    );
+                }
+                fs::read(plain).await?
+            }
+            (None, Some((data, plain))) => {
+                let source = plain
+                    .iter()
+                    .find(|x| x.name == target.name && !x.encrypted)
+                    .ok_or_else(|| anyhow::anyhow!("No plain copy of {}.", target.name))?;
+                if source.compression != target.compression {
+                    tracing::warn!(
+                        "Plain copy is {:?} but the entry is {:?}.",
+                        source.compression,
+                        target.compression
+                    );
+                }
+                data[source.data.clone()].to_vec()
+            }
+            (None, None) => {
+                return Err(anyhow::anyhow!(
+                    "Either --plain or --plain-zip is required."
+                ))
+            }
+        };
+        tracing::info!(
+            "Attacking {} with {} bytes of known plaintext at offset {}.",
+            target.name,
+            plaintext.len(),
+            offset
+        );
+
+        // The last header byte is the check byte, which extends plaintext known from the start.
+        let (plaintext, start) = match offset {
+            0 => (
+                [&[target.check], plaintext.as_slice()].concat(),
+                HEADER_SIZE - 1,
+            ),
+            _ => (plaintext, HEADER_SIZE + offset),
+        };
+        let ciphertext = data[target.data.clone()].to_vec();
+        let keys = task::spawn_blocking(move || {
+            Known {
+                ciphertext: &ciphertext,
+                plaintext: &plaintext,
+                offset: start,
+            }
+            .attack()
+        })
+        .await??
+        .ok_or_else(|| anyhow::anyhow!("No keys found."))?;
+        tracing::info!(
+            "Found keys x={:#010x}, y={:#010x}, z={:#010x}.",
+            keys.x,
+            keys.y,
+            keys.z
+        );
+
+        let stem = PathBuf::from(&file)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .unwrap()
+            .to_owned();
+        let outdir = PathBuf::from(&stem);
+        if !outdir.is_dir() {
+            fs::create_dir_all(&outdir).await?;
+        }
+        for entry in entries.iter().filter(|x| x.encrypted && !x.aes) {
+            let Some(plain) = Self::decrypt(&data, entry, keys)? else {
+                tracing::warn!("Check byte of {} does not match, skipped.", entry.name);
+                continue;
+            };
+            let Some(path) = entry.enclosed.as_ref().map(|x| outdir.join(x)) else {
+                tracing::warn!("Unsafe name {}, skipped.", entry.name);
+                continue;
+            };
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            let crc_ok = crc32fast::hash(&plain) == entry.crc;
+            fs::write(&path, &plain).await?;
+            tracing::info!(
+                "Decrypted {} as ({:?}), crc ok({}).",
+                entry.name,
+                path,
+                crc_ok
+            );
+        }
+
+        if let Some(password) = password {
+            let path = format!("{}-password.zip", stem);
+            fs::write(&path, Self::repack(&data, &entries, keys, &password)).await?;
+            tracing::info!(
+                "Archive with password ({}) saved as ({:?}).",
+                password,
+                path
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use zip::ZipArchive;
+
+    use super::ZipKpa;
+    use crate::zip_crypto::{self, encrypted, Keys};
+
+    const FILES: [(&str, &[u8], &[u8], bool); 3] = [
+        ("a.txt", b"flag{stored}", b"old", false),
+        ("b.txt", b"flag{deflated deflated deflated}", b"old", true),
+        ("c.txt", b"flag{other}", b"other", false),
+    ];
+
+    #[test]
+    fn test_decrypt() {
+        let data = encrypted(&FILES);
+        let entries = zip_crypto::entries(&data).unwrap();
+        let keys = Keys::new(b"old");
+        for (entry, (_, plain, password, _)) in entries.iter().zip(FILES) {
+            let decrypted = ZipKpa::decrypt(&data, entry, keys).unwrap();
+            match password {
+                b"old" => assert_eq!(decrypted.unwrap(), plain),
+                _ => assert!(decrypted.is_none()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_repack() {
+        let data = encrypted(&FILES);
+        let entries = zip_crypto::entries(&data).unwrap();
+        let repacked = ZipKpa::repack(&data, &entries, Keys::new(b"old"), "new");
+        assert_eq!(repacked.len(), data.len());
+
+        let mut zip = ZipArchive::new(Cursor::new(repacked.as_slice())).unwrap();
+        for (i, (_, plain, password, _)) in FILES.iter().enumerate() {
+            let password = match *password {
+                b"old" => &b"new"[..],
+                other => other,
+            };
+            let mut file = zip.by_index_decrypt(i, password).unwrap().unwrap();
+            let mut read = Vec::new();
+            file.read_to_end(&mut read).unwrap();
+            assert_eq!(&read, plain);
+        }
+
+        // The entry under another password passes through untouched.
+        let other = &entries[2].data;
+        assert_eq!(repacked[other.clone()], data[other.clone()]);
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    io::{self, Cursor},
+    num::NonZeroUsize,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::{fs, task};
+use zip::ZipArchive;
+
+use crate::{
+    mask,
+    zip_crypto::{self, Entry, Keys, HEADER_SIZE},
+    Command,
+};
+
+/// Progress is logged once per this many work items.
+const PROGRESS: usize = 4096;
+
+#[derive(Debug)]
+pub struct ZipPass {
+    file: String,
+    entry: Option<String>,
+    wordlist: Option<String>,
+    mask: Option<String>,
+}
+
+/// One thread's handle on the target entry.
+#[derive(Clone, Debug)]
+struct Tester<'a> {
+    data: &'a [u8],
+    entry: &'a Entry,
+    index: usize,
+    zip: ZipArchive<Cursor<&'a [u8]>>,
+}
+
+impl Tester<'_> {
+    /// Tries `password`, whose ZipCrypto keys are `keys`. The check byte or the AES verifier
+    /// rejects almost every wrong password before the entry is decompressed to check its CRC
+    /// or authentication code.
+    fn test(&mut self, password: &[u8], keys: Keys) -> bool {
+        if !self.entry.aes {
+            let start = self.entry.data.start;
+            let Some(header) = self.data.get(start..start + HEADER_SIZE) else {
+                return false;
+            };
+            let mut header = header.to_vec();
+            let mut keys = keys;
+            keys.decrypt(&mut header);
+            if header[HEADER_SIZE - 1] != self.entry.check {
+                return false;
+            }
+        }
+
+        // The zip crate checks the AES verifier itself before reading any data.
+        match self.zip.by_index_decrypt(self.index, password) {
+            Ok(Ok(mut file)) => io::copy(&mut file, &mut io::sink()).is_ok(),
+            _ => false,
+        }
+    }
+}
+
+impl ZipPass {
+    pub fn new(
+        file: String,
+        entry: Option<String>,
+        wordlist: Option<String>,
+        mask: Option<String>,
+    ) -> Self {
+        Self {
+            file,
+            entry,
+            wordlist,
+            mask,
+        }
+    }
+
+    /// Hands out work items `0..len` to a thread per core until one of them finds the password.
+    fn parallel<F>(tester: &Tester, len: usize, work: F) -> Option<Vec<u8>>
+    where
+        F: Fn(&mut Tester, usize) -> Option<Vec<u8>> + Sync,
+    {
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let (next, done) = (AtomicUsize::new(0), AtomicBool::new(false));
+        thread::scope(|s| {
+            let handles = (0..threads)
+                .map(|_| {
+                    let mut tester = tester.clone();
+                    let (next, done, work) = (&next, &done, &work);
+                    s.spawn(move || {
+                        while !done.load(Ordering::Relaxed) {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            if i >= len {
+                                break;
+                            }
+                            if i % PROGRESS == 0 {
+                                tracing::debug!("Trying item {}/{}.", i, len);
+                            }
+
+                            if let Some(password) = work(&mut tester, i) {
+                                done.store(true, Ordering::Relaxed);
+                                return Some(password);
+                            }
+                        }
+                        None
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .filter_map(|x| x.join().expect("Failed to join password thread."))
+                .next()
+        })
+    }
+
+    fn search_words(tester: &Tester, words: &[&[u8]]) -> Option<Vec<u8>> {
+        Self::parallel(tester, words.len(), |tester, i| {
+            let word = words[i];
+            tester.test(word, Keys::new(word)).then(|| word.to_vec())
+        })
+    }
+
+    fn search_mask(tester: &Tester, mask: &[Vec<u8>]) -> Option<Vec<u8>> {
+        let (split, items) = mask::split(mask);
+        Self::parallel(tester, items, |tester, i| {
+            let prefix = mask::nth(&mask[..split], i);
+            Self::brute(tester, prefix, &mask[split..])
+        })
+    }
+
+    fn brute(tester: &mut Tester, curr: Vec<u8>, rest: &[Vec<u8>]) -> Option<Vec<u8>> {
+        let keys = Keys::new(&curr);
+        let step = |mut keys: Keys, b| {
+            keys.update(b);
+            keys
+        };
+        mask::walk(curr, keys, rest, step, |pt, keys| tester.test(pt, keys))
+    }
+}
+
+#[async_trait]
+impl Command for ZipPass {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self {
+            file,
+            entry,
+            wordlist,
+            mask,
+        } = *self;
+
+        let data = fs::read(&file).await?;
+        let words = match &wordlist {
+            Some(wordlist) => Some(fs::read(wordlist).await?),
+            None => None,
+        };
+        let mask = mask.as_deref().map(|x| mask::parse(x, None)).transpose()?;
+
+        let found = task::spawn_blocking(move || -> Result<_> {
+            let entries = zip_crypto::entries(&data)?;
+            let (index, target) = entries
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| x.encrypted)
+                .find(|(_, x)| entry.as_ref().is_none_or(|name| &x.name == name))
+                .ok_or_else(|| anyhow::anyhow!("No matching encrypted entry found."))?;
+            let tester = Tester {
+                data: &data,
+                entry: target,
+                index,
+                zip: ZipArchive::new(Cursor::new(data.as_slice()))?,
+            };
+
+            let password = match (&words, &mask) {
+                (Some(words), _) => {
+                    let words = words
+                        .split(|&x| x == b'\n')
+                        .map(|x| x.strip_suffix(b"\r").unwrap_or(x))
+                        .filter(|x| !x.is_empty())
+                        .collect::<Vec<_>>();
+                    tracing::info!(
+                        "Trying {} words on {} (aes {}).",
+                        words.len(),
+                        target.name,
+                        target.aes
+                    );
+                    Self::search_words(&tester, &words)
+                }
+                (None, Some(mask)) => {
+                    let count = mask
+                        .iter()
+                        .fold(1_u128, |acc, x| acc.saturating_mul(x.len() as u128));
+                    tracing::info!(
+                        "Trying {} masked passwords on {} (aes {}).",
+                        count,
+                        target.name,
+                        target.aes
+                    );
+                    Self::search_mask(&tester, mask)
+                }
+                (None, None) => {
+                    return Err(anyhow::anyhow!("Either --wordlist or --mask is required."))
+                }
+            };
+
+            Ok(password.map(|x| (target.name.clone(), x)))
+        })
+        .await??;
+
+        match found {
+            Some((name, password)) => tracing::info!(
+                "Found password ({}) for {}, hex({}).",
+                String::from_utf8_lossy(&password),
+                name,
+                hex::encode(&password)
+            ),
+            None => tracing::info!("No password found."),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use zip::ZipArchive;
+
+    use super::{Tester, ZipPass};
+    use crate::{
+        mask,
+        zip_crypto::{self, encrypted},
+    };
+
+    #[test]
+    fn test_search() {
+        let data = encrypted(&[("flag.txt", b"flag{zip_password}", b"pw42", false)]);
+        let entries = zip_crypto::entries(&data).unwrap();
+        assert!(entries[0].encrypted && !entries[0].aes);
+        let tester = Tester {
+            data: &data,
+            entry: &entries[0],
+            index: 0,
+            zip: ZipArchive::new(Cursor::new(data.as_slice())).unwrap(),
+        };
+
+        let words = [&b"password"[..], b"pw41", b"pw42", b"pw43"];
+        assert_eq!(ZipPass::search_words(&tester, &words).unwrap(), b"pw42");
+        assert!(ZipPass::search_words(&tester, &words[..2]).is_none());
+
+        let mask = mask::parse("?lw?d?d", None).unwrap();
+        assert_eq!(ZipPass::search_mask(&tester, &mask).unwrap(), b"pw42");
+        let mask = mask::parse("pw?d", None).unwrap();
+        assert!(ZipPass::search_mask(&tester, &mask).is_none());
+
+        // WinZip AES-256 (AE-2) of "flag{zip_aes}" with password "pw42", written by bsdtar.
+        let data = hex::decode(concat!(
+            "504b030414000900630068a6505d00000000000000000000000007002b006165",
+            "732e74787475780b000104000000000400000000019907000200414503000055",
+            "540d0007448ed26a448ed26a448ed26a712903d5f48c5bb11847cee65be12742",
+            "d7d1128b96671a4ede59b22971bb12c387b27022522becc6e5504b0708000000",
+            "00290000000d000000504b0102140314000900630068a6505d00000000290000",
+            "000d000000070023000000000000000000a481000000006165732e7478747578",
+            "0b00010400000000040000000001990700020041450300005554050001448ed2",
+            "6a504b0506000000000100010058000000890000000000",
+        ))
+        .unwrap();
+        let entries = zip_crypto::entries(&data).unwrap();
+        assert!(entries[0].encrypted && entries[0].aes);
+        let tester = Tester {
+            data: &data,
+            entry: &entries[0],
+            index: 0,
+            zip: ZipArchive::new(Cursor::new(data.as_slice())).unwrap(),
+        };
+        assert_eq!(ZipPass::search_words(&tester, &words).unwrap(), b"pw42");
+        assert!(ZipPass::search_words(&tester, &words[..2]).is_none());
+        let mask = mask::parse("pw4?d", None).unwrap();
+        assert_eq!(ZipPass::search_mask(&tester, &mask).unwrap(), b"pw42");
+    }
+}
@@ -0,0 +1,153 @@
+use std::{io::Read, path::PathBuf};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use flate2::read::DeflateDecoder;
+use tokio::fs;
+use zip::CompressionMethod;
+
+use crate::{
+    zip_crypto::{self, Entry},
+    Command,
+};
+
+#[derive(Debug)]
+pub struct ZipPseudo {
+    file: String,
+}
+
+impl ZipPseudo {
+    pub fn new(file: String) -> Self {
+        Self { file }
+    }
+
+    fn flags(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]])
+    }
+
+    /// Whether the raw data already reads as the plaintext, `None` when the compression
+    /// method cannot be checked.
+    fn is_plain(data: &[u8], entry: &Entry) -> Option<bool> {
+        let raw = &data[entry.data.clone()];
+        match entry.compression {
+            CompressionMethod::Stored => Some(crc32fast::hash(raw) == entry.crc),
+            CompressionMethod::Deflated => {
+                let mut plain = Vec::new();
+                let ok = DeflateDecoder::new(raw).read_to_end(&mut plain).is_ok();
+                Some(ok && crc32fast::hash(&plain) == entry.crc)
+            }
+            _ => None,
+        }
+    }
+
+    /// Clears the encryption flag of every entry whose data is not actually encrypted,
+    /// returning how many entries changed.
+    fn clean(data: &mut [u8], entries: &[Entry]) -> usize {
+        let mut cleaned = 0;
+        for entry in entries {
+            let (local, central) = (entry.local + 6, entry.central + 8);
+            let flagged = (Self::flags(data, local) | Self::flags(data, central)) & 0x01 != 0;
+            if !flagged {
+                continue;
+            }
+
+            match Self::is_plain(data, entry) {
+                Some(true) => {
+                    tracing::info!(
+                        "{} is pseudo-encrypted, local({}) central({}).",
+                        entry.name,
+                        Self::flags(data, local) & 0x01,
+                        Self::flags(data, central) & 0x01
+                    );
+                    for offset in [local, central] {
+                        let flags = Self::flags(data, offset) & !0x01;
+                        data[offset..offset + 2].copy_from_slice(&flags.to_le_bytes());
+                    }
+                    cleaned += 1;
+                }
+                Some(false) => tracing::info!("{} is really encrypted.", entry.name),
+                None => tracing::warn!(
+                    "Cannot check {} compressed with {:?}, skipped.",
+                    entry.name,
+                    entry.compression
+                ),
+            }
+        }
+
+        cleaned
+    }
+}
+
+#[async_trait]
+impl Command for ZipPseudo {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self { file } = *self;
+
+        let mut data = fs::read(&file).await?;
+        let entries = zip_crypto::entries(&data)?;
+        if Self::clean(&mut data, &entries) == 0 {
+            tracing::info!("No pseudo-encrypted entries found.");
+            return Ok(());
+        }
+
+        let path = PathBuf::from(file)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .map(|x| format!("{}-fixed.zip", x))
+            .unwrap();
+        fs::write(&path, data).await?;
+        tracing::info!("Cleaned zip saved as ({:?}).", path);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+    use super::ZipPseudo;
+    use crate::zip_crypto;
+
+    #[test]
+    fn test_clean() {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, method) in [
+            ("stored", CompressionMethod::Stored),
+            ("deflated", CompressionMethod::Deflated),
+            ("encrypted", CompressionMethod::Stored),
+        ] {
+            zip.start_file(name, FileOptions::default().compression_method(method))
+                .unwrap();
+            zip.write_all(b"flag{pseudo_encryption}").unwrap();
+        }
+        let mut data = zip.finish().unwrap().into_inner();
+
+        let entries = zip_crypto::entries(&data).unwrap();
+        assert_eq!(ZipPseudo::clean(&mut data.clone(), &entries), 0);
+
+        // Flag the first entry locally, the others centrally, and garble the last one's data
+        // so it looks really encrypted.
+        data[entries[0].local + 6] |= 0x01;
+        data[entries[1].central + 8] |= 0x01;
+        data[entries[2].central + 8] |= 0x01;
+        data[entries[2].data.start] ^= 0xff;
+        let entries = zip_crypto::entries(&data).unwrap();
+        assert!(entries[0].encrypted);
+        assert!(ZipArchive::new(Cursor::new(&data))
+            .unwrap()
+            .by_index(1)
+            .is_err());
+
+        assert_eq!(ZipPseudo::clean(&mut data, &entries), 2);
+        let mut zip = ZipArchive::new(Cursor::new(&data)).unwrap();
+        for i in 0..2 {
+            let mut plain = String::new();
+            zip.by_index(i).unwrap().read_to_string(&mut plain).unwrap();
+            assert_eq!(plain, "flag{pseudo_encryption}");
+        }
+        assert!(zip.by_index(2).is_err());
+    }
+}
@@ -0,0 +1,383 @@
+use std::{io::Read, ops::Range, path::PathBuf};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use flate2::read::DeflateDecoder;
+use tokio::fs;
+
+use crate::{error::Error, Command};
+
+const LOCAL: &[u8] = b"PK\x03\x04";
+const CENTRAL: &[u8] = b"PK\x01\x02";
+const END: &[u8] = b"PK\x05\x06";
+const DESCRIPTOR: &[u8] = b"PK\x07\x08";
+
+#[derive(Debug)]
+pub struct ZipRepair {
+    file: String,
+}
+
+/// An entry as found by its local file header.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Local {
+    version: u16,
+    flags: u16,
+    method: u16,
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed: u32,
+    size: u32,
+    name: Vec<u8>,
+    extra: Vec<u8>,
+    data: Range<usize>,
+}
+
+fn find(data: &[u8], from: usize, sig: &[u8]) -> Option<usize> {
+    data.get(from..)?
+        .windows(sig.len())
+        .position(|x| x == sig)
+        .map(|x| x + from)
+}
+
+/// Inflates `data` into the bytes consumed and the CRC and size of the output, reading at most
+/// one byte past `limit` so an oversized entry shows without holding it in memory.
+fn inflate(data: &[u8], limit: u64) -> Option<(usize, u32, u64)> {
+    let mut decoder = DeflateDecoder::new(data);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut size = 0;
+    let mut buf = [0; 1 << 16];
+    let mut reader = (&mut decoder).take(limit + 1);
+    loop {
+        let len = reader.read(&mut buf).ok()?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+        size += len as u64;
+    }
+
+    Some((decoder.total_in() as usize, hasher.finalize(), size))
+}
+
+impl Local {
+    fn parse(data: &[u8], offset: usize) -> Option<Self> {
+        let header = data.get(offset..offset + 30)?;
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        let name = offset + 30..offset + 30 + u16_at(26) as usize;
+        let extra = name.end..name.end + u16_at(28) as usize;
+
+        Some(Self {
+            version: u16_at(4),
+            flags: u16_at(6),
+            method: u16_at(8),
+            time: u16_at(10),
+            date: u16_at(12),
+            crc: u32_at(14),
+            compressed: u32_at(18),
+            size: u32_at(22),
+            name: data.get(name)?.to_vec(),
+            extra: data.get(extra.clone())?.to_vec(),
+            data: extra.end..extra.end,
+        })
+    }
+
+    /// Works out where the data really ends and what it decodes to, then corrects the header
+    /// fields that disagree.
+    fn fix(&mut self, data: &[u8]) -> Result<(), Error> {
+        let start = self.data.start;
+        let encrypted = self.flags & 0x01 != 0;
+        let boundary = [LOCAL, CENTRAL, END]
+            .iter()
+            .filter_map(|x| find(data, start, x))
+            .min()
+            .unwrap_or(data.len());
+
+        // Trust the declared size when it checks out, a stored CRC or a signature right after
+        // the data, since scanning stops at the first signature even inside a stored archive.
+        let declared = start
+            .checked_add(self.compressed as usize)
+            .filter(|&x| x <= data.len() && (self.flags & 0x08 == 0 || self.compressed != 0));
+        let checks_out = |end: usize| match (self.method, encrypted) {
+            (0, false) => crc32fast::hash(&data[start..end]) == self.crc,
+            _ => {
+                end == data.len()
+                    || [LOCAL, CENTRAL, END, DESCRIPTOR]
+                        .iter()
+                        .any(|x| data[end..].starts_with(x))
+            }
+        };
+
+        // The CRC and size of the plaintext, where it can be read.
+        let stored = |end: usize| {
+            (self.method == 0 && !encrypted)
+                .then(|| (crc32fast::hash(&data[start..end]), (end - start) as u64))
+        };
+        let (end, plain) = match (self.method, encrypted, declared) {
+            (8, false, _) => match inflate(&data[start..], u32::MAX as u64) {
+                Some((len, crc, size)) => (start + len, Some((crc, size))),
+                None => (boundary, None),
+            },
+            (_, _, Some(end)) if checks_out(end) => (end, stored(end)),
+            _ => {
+                // Without a descriptor the declared size is the best guess, otherwise the
+                // data runs up to the descriptor or the next header.
+                let descriptor = data[start..boundary]
+                    .windows(DESCRIPTOR.len())
+                    .rposition(|x| x == DESCRIPTOR)
+                    .map(|x| start + x);
+                let candidates = [declared, descriptor, Some(boundary)]
+                    .into_iter()
+                    .flatten()
+                    .filter(|&x| x <= boundary)
+                    .collect::<Vec<_>>();
+
+                let end = match (self.method, encrypted) {
+                    (0, false) => candidates
+                        .iter()
+                        .find(|&&x| crc32fast::hash(&data[start..x]) == self.crc)
+                        .or(candidates.first()),
+                    _ => candidates.first(),
+                };
+                let end = end.copied().unwrap_or(boundary);
+                (end, stored(end))
+            }
+        };
+        self.data.end = end;
+
+        // Bit 3 defers the CRC and sizes to a descriptor right after the data.
+        let (mut crc, mut size) = (self.crc, self.size);
+        if self.flags & 0x08 != 0 {
+            let skip = match data[end..].starts_with(DESCRIPTOR) {
+                true => DESCRIPTOR.len(),
+                false => 0,
+            };
+            let at = end + skip;
+            if let Some(descriptor) = data.get(at..at + 12) {
+                let u32_at =
+                    |i: usize| u32::from_le_bytes(descriptor[i..i + 4].try_into().unwrap());
+                (crc, size) = (u32_at(0), u32_at(8));
+            }
+        }
+        let name = String::from_utf8_lossy(&self.name).into_owned();
+        let zip64 = || Error::Zip64(name.clone());
+        let compressed = u32::try_from(end - start).map_err(|_| zip64())?;
+        if let Some((plain_crc, plain_size)) = plain {
+            (crc, size) = (plain_crc, u32::try_from(plain_size).map_err(|_| zip64())?);
+        } else if self.method == 0 && encrypted && self.flags & 0x40 == 0 {
+            // Stored ZipCrypto data is the plaintext behind a 12 byte encryption header.
+            size = compressed.saturating_sub(12);
+        }
+
+        for (field, old, new) in [
+            ("crc", &mut self.crc, crc),
+            ("compressed", &mut self.compressed, compressed),
+            ("size", &mut self.size, size),
+        ] {
+            if *old != new {
+                tracing::info!("Fixed {} {}({:#x}) -> ({:#x}).", name, field, old, new);
+                *old = new;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The fields local and central headers share, from the version needed to the extra length.
+    fn fields(&self) -> Vec<u8> {
+        [
+            &self.version.to_le_bytes()[..],
+            &self.flags.to_le_bytes(),
+            &self.method.to_le_bytes(),
+            &self.time.to_le_bytes(),
+            &self.date.to_le_bytes(),
+            &self.crc.to_le_bytes(),
+            &self.compressed.to_le_bytes(),
+            &self.size.to_le_bytes(),
+            &(self.name.len() as u16).to_le_bytes(),
+            &(self.extra.len() as u16).to_le_bytes(),
+        ]
+        .concat()
+    }
+}
+
+/// Rebuilds an archive from the local file headers alone, ignoring whatever central directory
+/// is left.
+pub fn recover(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut locals = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = find(data, pos, LOCAL) {
+        let Some(mut local) = Local::parse(data, offset) else {
+            pos = offset + LOCAL.len();
+            continue;
+        };
+        local.fix(data)?;
+        tracing::debug!(offset, ?local);
+        pos = local.data.end;
+        locals.push(local);
+    }
+    if locals.is_empty() {
+        return Err(Error::ZipLocal);
+    }
+
+    let (mut archive, mut central) = (Vec::new(), Vec::new());
+    for local in &locals {
+        let offset = u32::try_from(archive.len())
+            .map_err(|_| Error::Zip64(String::from_utf8_lossy(&local.name).into_owned()))?;
+        let fields = local.fields();
+        archive.extend_from_slice(LOCAL);
+        archive.extend_from_slice(&fields);
+        archive.extend_from_slice(&local.name);
+        archive.extend_from_slice(&local.extra);
+        archive.extend_from_slice(&data[local.data.clone()]);
+        if local.flags & 0x08 != 0 {
+            archive.extend_from_slice(DESCRIPTOR);
+            archive.extend_from_slice(&fields[10..22]);
+        }
+
+        let external: u32 = if local.name.ends_with(b"/") { 0x10 } else { 0 };
+        central.extend_from_slice(CENTRAL);
+        central.extend_from_slice(&20_u16.to_le_bytes());
+        central.extend_from_slice(&fields);
+        central.extend_from_slice(&[0; 6]);
+        central.extend_from_slice(&external.to_le_bytes());
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(&local.name);
+        central.extend_from_slice(&local.extra);
+    }
+
+    let directory = || Error::Zip64("the central directory".to_owned());
+    let count = u16::try_from(locals.len()).map_err(|_| directory())?;
+    let offset = u32::try_from(archive.len()).map_err(|_| directory())?;
+    let size = u32::try_from(central.len()).map_err(|_| directory())?;
+    archive.extend_from_slice(&central);
+    archive.extend_from_slice(END);
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&count.to_le_bytes());
+    archive.extend_from_slice(&count.to_le_bytes());
+    archive.extend_from_slice(&size.to_le_bytes());
+    archive.extend_from_slice(&offset.to_le_bytes());
+    archive.extend_from_slice(&[0; 2]);
+
+    Ok(archive)
+}
+
+impl ZipRepair {
+    pub fn new(file: String) -> Self {
+        Self { file }
+    }
+}
+
+#[async_trait]
+impl Command for ZipRepair {
+    async fn execute(self: Box<Self>) -> Result<()> {
+        let Self { file } = *self;
+
+        let data = fs::read(&file).await?;
+        let archive = recover(&data)?;
+
+        let path = PathBuf::from(file)
+            .file_stem()
+            .and_then(|x| x.to_str())
+            .map(|x| format!("{}-fixed.zip", x))
+            .unwrap();
+        fs::write(&path, archive).await?;
+        tracing::info!("Repaired zip saved as ({:?}).", path);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use flate2::{write::DeflateEncoder, Compression};
+    use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+    use super::{find, inflate, recover, CENTRAL};
+
+    #[test]
+    fn test_inflate() {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[7; 1000]).unwrap();
+        let data = encoder.finish().unwrap();
+
+        let (len, crc, size) = inflate(&data, 1000).unwrap();
+        assert_eq!(
+            (len, crc, size),
+            (data.len(), crc32fast::hash(&[7; 1000]), 1000)
+        );
+        // An entry too large for the headers stops one byte past the limit.
+        assert_eq!(inflate(&data, 100).unwrap().2, 101);
+    }
+
+    #[test]
+    fn test_recover() {
+        let deflated = "flag{zip_repair}".repeat(64);
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, method, pt) in [
+            ("stored", CompressionMethod::Stored, "flag{"),
+            ("deflated", CompressionMethod::Deflated, deflated.as_str()),
+        ] {
+            zip.start_file(name, FileOptions::default().compression_method(method))
+                .unwrap();
+            zip.write_all(pt.as_bytes()).unwrap();
+        }
+        let mut data = zip.finish().unwrap().into_inner();
+
+        // Drop the central directory and garble the stored CRC and the deflated sizes.
+        data.truncate(find(&data, 0, CENTRAL).unwrap());
+        data[14..18].fill(0);
+        let deflated_header = 30 + "stored".len() + 5;
+        data[deflated_header + 18..deflated_header + 26].fill(0xff);
+        assert!(ZipArchive::new(Cursor::new(&data)).is_err());
+
+        let mut zip = ZipArchive::new(Cursor::new(recover(&data).unwrap())).unwrap();
+        assert_eq!(zip.len(), 2);
+        for (i, pt) in ["flag{", deflated.as_str()].into_iter().enumerate() {
+            let mut plain = String::new();
+            zip.by_index(i).unwrap().read_to_string(&mut plain).unwrap();
+            assert_eq!(plain, pt);
+        }
+
+        assert!(recover(b"not a zip").is_err());
+    }
+
+    #[test]
+    fn test_recover_nested() {
+        let stored = || FileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut inner = ZipWriter::new(Cursor::new(Vec::new()));
+        inner.start_file("a.txt", stored()).unwrap();
+        inner.write_all(b"inner").unwrap();
+        let inner = inner.finish().unwrap().into_inner();
+
+        let mut outer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, pt) in [
+            ("inner.zip", inner.as_slice()),
+            ("b.txt", b"outer"),
+            ("c.bin", &[0x5a; 17]),
+        ] {
+            outer.start_file(name, stored()).unwrap();
+            outer.write_all(pt).unwrap();
+        }
+        let mut data = outer.finish().unwrap().into_inner();
+        // The inner archive has its own central directory, so cut at the outer one's offset.
+        let end = data.len() - 22;
+        let central = u32::from_le_bytes(data[end + 16..end + 20].try_into().unwrap());
+        data.truncate(central as usize);
+
+        // Pretend the last entry is stored with ZipCrypto, whose size hides a 12 byte header.
+        let last = data.len() - 17 - 30 - "c.bin".len();
+        data[last + 6] |= 0x01;
+        data[last + 22..last + 26].fill(0xff);
+
+        let mut zip = ZipArchive::new(Cursor::new(recover(&data).unwrap())).unwrap();
+        assert_eq!(zip.len(), 3);
+        let mut plain = Vec::new();
+        zip.by_index(0).unwrap().read_to_end(&mut plain).unwrap();
+        assert_eq!(plain, inner);
+        assert_eq!(zip.by_index_raw(2).unwrap().size(), 5);
+    }
+}